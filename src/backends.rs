@@ -0,0 +1,121 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{ffi::OsString, path::Path};
+
+use crate::programs::Program;
+
+/// Program used to actually put the image on screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Swww,
+    Hyprpaper,
+    Swaybg,
+    Wbg,
+    Feh,
+    Xwallpaper,
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Swww => "swww",
+            Backend::Hyprpaper => "hyprpaper",
+            Backend::Swaybg => "swaybg",
+            Backend::Wbg => "wbg",
+            Backend::Feh => "feh",
+            Backend::Xwallpaper => "xwallpaper",
+        }
+    }
+
+    /// Whether the backend can target individual outputs
+    pub fn supports_outputs(&self) -> bool {
+        matches!(self, Backend::Swww)
+    }
+
+    /// Commands that set `path` as the wallpaper with this backend.
+    /// `outputs` is only honored by backends that support it; empty means every output.
+    pub fn programs(&self, path: &Path, outputs: &[String]) -> Vec<Program> {
+        match self {
+            Backend::Swww => vec![Program::swww_outputs(path, outputs)],
+            Backend::Hyprpaper => hyprpaper_programs(path),
+            Backend::Swaybg => vec![Program::swaybg(path)],
+            Backend::Wbg => vec![Program::wbg(path)],
+            Backend::Feh => vec![Program::feh(path)],
+            Backend::Xwallpaper => vec![Program::xwallpaper(path)],
+        }
+    }
+}
+
+fn hyprpaper_programs(path: &Path) -> Vec<Program> {
+    let hyprctl = |args: Vec<OsString>| Program::custom("hyprpaper", "hyprctl", args);
+
+    // An empty monitor name before the comma applies the wallpaper to every monitor
+    let mut target = OsString::from(",");
+    target.push(path.as_os_str());
+
+    vec![
+        hyprctl(vec![
+            OsString::from("hyprpaper"),
+            OsString::from("preload"),
+            path.as_os_str().to_os_string(),
+        ]),
+        hyprctl(vec![
+            OsString::from("hyprpaper"),
+            OsString::from("wallpaper"),
+            target,
+        ]),
+        hyprctl(vec![
+            OsString::from("hyprpaper"),
+            OsString::from("unload"),
+            OsString::from("unused"),
+        ]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_backend_is_swww() {
+        assert_eq!(Backend::default(), Backend::Swww);
+    }
+
+    #[test]
+    fn test_backend_step_names() {
+        let path = Path::new("/test/image.jpg");
+
+        for backend in Backend::value_variants() {
+            for program in backend.programs(path, &[]) {
+                assert_eq!(program.get_step_name(), backend.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_hyprpaper_programs() {
+        let programs = Backend::Hyprpaper.programs(Path::new("/test/image.jpg"), &[]);
+
+        assert_eq!(programs.len(), 3);
+        match &programs[1] {
+            Program::Custom { program, args, .. } => {
+                assert_eq!(program, "hyprctl");
+                assert_eq!(args, &vec!["hyprpaper", "wallpaper", ",/test/image.jpg"]);
+            }
+            _ => panic!("Expected Custom variant"),
+        }
+    }
+
+    #[test]
+    fn test_backend_from_config_name() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            backend: Backend,
+        }
+
+        let wrapper: Wrapper = toml::from_str(r#"backend = "xwallpaper""#).unwrap();
+        assert_eq!(wrapper.backend, Backend::Xwallpaper);
+    }
+}
@@ -1,36 +1,777 @@
-use clap::Parser;
-use std::path::PathBuf;
+use anyhow::{Result, anyhow};
+use clap::{Args, Parser, Subcommand};
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::backends::Backend;
+use crate::generators::Generator;
+use crate::history::{HistoryFilter, HistoryFormat, TimeSpec};
+use crate::logging::warning;
+use crate::outputs::OutputArg;
+use crate::tags::normalize_tags;
+
+pub const DEFAULT_MATUGEN_TYPE: &str = "scheme-tonal-spot";
+/// Shortest slideshow interval; anything shorter reruns the pipeline nonstop
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Parser, Debug)]
 #[command(name = "wallpaper")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Dry run mode - show what would be executed without running commands
+    #[arg(long = "dry-run", global = true)]
+    pub dry_run: bool,
+
+    /// Only print warnings, errors and the output a command was asked for
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print more details: -v adds exit status and duration of every program, -vv also file locations and step settings
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Number of history entries to keep, 0 for unlimited (overrides `max_entries` in config.toml)
+    #[arg(long, value_name = "N", global = true)]
+    pub history_size: Option<usize>,
+
+    /// Drop history entries older than this, e.g. 90d (overrides `max_age` in config.toml)
+    #[arg(long, value_name = "DURATION", global = true, value_parser = humantime::parse_duration)]
+    pub history_max_age: Option<Duration>,
+
+    /// Palette options given before the subcommand apply to it as well
+    #[command(flatten)]
+    pub palette: PaletteArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    #[command(flatten)]
+    pub legacy: LegacyArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Set the wallpaper from an image file
+    Set(SetArgs),
+
+    /// Choose the wallpaper with a graphical file chooser
+    Gui(GuiArgs),
+
+    /// Set a random wallpaper from a directory
+    Random(RandomArgs),
+
+    /// Rotate wallpapers from a directory or playlist file on an interval
+    Daemon(DaemonArgs),
+
+    /// Show wallpaper history
+    History(HistoryArgs),
+
+    /// Re-apply a wallpaper from history
+    Restore(RestoreArgs),
+
+    /// Step back to the previous wallpaper in history without reordering it
+    Prev(NavigateArgs),
+
+    /// Step forward to the next newer wallpaper after `prev`
+    Next(NavigateArgs),
+
+    /// Return to the most recent wallpaper after `prev`
+    BackToLatest(NavigateArgs),
+
+    /// Print the current wallpaper (most recent history entry, or where `prev`/`next` moved to)
+    Current,
+
+    /// Manage image tags used by `history --tag` and `random --tag`
+    Tag(TagArgs),
+}
+
+/// How the palette is generated
+#[derive(Args, Debug, Default, Clone)]
+pub struct PaletteArgs {
     /// Use light palette mode
     #[arg(short = 'l', long)]
     pub light: bool,
 
-    /// Open graphical file chooser
-    #[arg(long, conflicts_with = "path")]
-    pub gui: bool,
+    /// matugen scheme type [values: scheme-content, scheme-expressive, scheme-fidelity, scheme-fruit-salad, scheme-monochrome, scheme-neutral, scheme-rainbow, scheme-tonal-spot] [default: scheme-tonal-spot]
+    #[arg(long = "type", value_name = "TYPE")]
+    pub matugen_type: Option<String>,
+}
+
+impl PaletteArgs {
+    pub fn matugen_type(&self) -> &str {
+        self.matugen_type.as_deref().unwrap_or(DEFAULT_MATUGEN_TYPE)
+    }
 
-    /// matugen scheme type [values: scheme-content, scheme-expressive, scheme-fidelity, scheme-fruit-salad, scheme-monochrome, scheme-neutral, scheme-rainbow, scheme-tonal-spot]
-    #[arg(
-        long = "type",
-        value_name = "TYPE",
-        default_value = "scheme-tonal-spot"
-    )]
-    pub matugen_type: String,
+    /// Options given to the subcommand win over the ones given before it
+    fn merge(self, outer: PaletteArgs) -> Self {
+        Self {
+            light: self.light || outer.light,
+            matugen_type: self.matugen_type.or(outer.matugen_type),
+        }
+    }
+}
 
-    /// Path to image (ignored if --gui is used)
+/// Which programs run
+#[derive(Args, Debug, Default, Clone)]
+pub struct PipelineArgs {
+    /// Wallpaper setter backend (overrides `backend` in config.toml)
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+
+    /// Palette generators to run, comma-separated or repeated (overrides `generators` in config.toml)
+    #[arg(long = "generator", value_enum, value_delimiter = ',')]
+    pub generators: Vec<Generator>,
+}
+
+impl PipelineArgs {
+    /// Options given to the subcommand win over the ones given before it
+    fn merge(self, outer: PipelineArgs) -> Self {
+        Self {
+            backend: self.backend.or(outer.backend),
+            generators: if self.generators.is_empty() {
+                outer.generators
+            } else {
+                self.generators
+            },
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SetArgs {
+    /// Path to image
+    #[arg(required_unless_present = "outputs")]
     pub path: Option<PathBuf>,
 
-    /// Show wallpaper history
+    /// Set the wallpaper only on this output (NAME), or give each output its own image
+    /// (NAME=PATH, repeat for every monitor; no PATH argument then). swww backend only.
+    #[arg(long = "output", value_name = "NAME[=PATH]")]
+    pub outputs: Vec<OutputArg>,
+
+    /// Output whose image is used for palette generation in per-output mode
+    /// (defaults to the first NAME=PATH)
+    #[arg(long, value_name = "NAME", requires = "outputs")]
+    pub primary: Option<String>,
+
+    #[command(flatten)]
+    pub palette: PaletteArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct GuiArgs {
+    #[command(flatten)]
+    pub palette: PaletteArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RandomArgs {
+    /// Directory to pick from (searched recursively)
+    pub dir: PathBuf,
+
+    /// Only consider these file extensions, comma-separated [default: common image types]
+    #[arg(long = "ext", value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// Only consider files whose path relative to DIR matches this glob (e.g. "nature/*")
     #[arg(long)]
+    pub glob: Option<String>,
+
+    /// Do not pick any of the N most recent wallpapers from history
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub avoid: usize,
+
+    /// Seed for a reproducible choice
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Only pick images with these tags, comma-separated or repeated (all must match)
+    #[arg(long = "tag", value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    #[command(flatten)]
+    pub palette: PaletteArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DaemonArgs {
+    /// Directory of images or playlist file with one image path per line
+    pub source: PathBuf,
+
+    /// Time between wallpaper changes, e.g. 30m, 1h 30m, 90s
+    #[arg(long, default_value = "30m", value_parser = parse_interval)]
+    pub interval: Duration,
+
+    #[command(flatten)]
+    pub palette: PaletteArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    pub action: Option<HistoryAction>,
+
+    /// Output format; json, ndjson and csv include every entry field
+    #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+    pub format: HistoryFormat,
+
+    /// Only list starred entries
+    #[arg(long)]
+    pub favorites: bool,
+
+    /// Only list images with these tags, comma-separated or repeated (all must match)
+    #[arg(long = "tag", value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// Only list entries whose path contains TEXT (case-insensitive)
+    #[arg(long, value_name = "TEXT")]
+    pub grep: Option<String>,
+
+    /// Only list entries with this matugen scheme type
+    #[arg(long = "type", value_name = "TYPE")]
+    pub matugen_type: Option<String>,
+
+    /// Only list light palette entries
+    #[arg(long, conflicts_with = "dark")]
+    pub light: bool,
+
+    /// Only list dark palette entries
+    #[arg(long)]
+    pub dark: bool,
+
+    /// Only list entries set at or after WHEN: a duration ago (2d, 1h 30m) or a UTC date (2026-10-01, 2026-10-01 18:30)
+    #[arg(long, value_name = "WHEN")]
+    pub since: Option<TimeSpec>,
+
+    /// Only list entries set before WHEN; a date includes that whole day
+    #[arg(long, value_name = "WHEN")]
+    pub until: Option<TimeSpec>,
+}
+
+impl HistoryArgs {
+    pub fn filter(&self) -> Result<HistoryFilter> {
+        let now = SystemTime::now();
+        let is_light = match (self.light, self.dark) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+
+        Ok(HistoryFilter {
+            favorites: self.favorites,
+            tags: normalize_tags(&self.tags)?,
+            grep: self.grep.clone(),
+            matugen_type: self.matugen_type.clone(),
+            is_light,
+            since: self.since.map(|t| t.resolve(now, false)),
+            until: self.until.map(|t| t.resolve(now, true)),
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryAction {
+    /// Star entry N so it is never evicted from history
+    Star(StarArgs),
+
+    /// Remove the star from entry N
+    Unstar(StarArgs),
+
+    /// Remove entries whose image file no longer exists
+    Gc,
+}
+
+#[derive(Args, Debug)]
+pub struct StarArgs {
+    /// History entry, 1 is the most recent (see `wallpaper history`)
+    #[arg(value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub step: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct NavigateArgs {
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    /// History entry to restore, 1 is the most recent (see `wallpaper history`)
+    #[arg(value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(i32).range(1..))]
+    pub step: i32,
+
+    /// Restore the starred wallpaper with this file name (or part of it) instead
+    #[arg(long, value_name = "NAME", conflicts_with = "step")]
+    pub favorite: Option<String>,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct TagArgs {
+    #[command(subcommand)]
+    pub action: TagAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagAction {
+    /// Add tags to an image
+    Add(TagEditArgs),
+
+    /// Remove tags from an image
+    Remove(TagEditArgs),
+
+    /// List the tags of an image, or every tag with its image count
+    List {
+        /// Image to list the tags of
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct TagEditArgs {
+    /// Path to image
+    pub path: PathBuf,
+
+    /// Tags, e.g. dark nature work
+    #[arg(required = true)]
+    pub tags: Vec<String>,
+}
+
+/// Flat options from before subcommands existed. Hidden from help and
+/// translated into the matching subcommand by [`Cli::into_command`].
+#[derive(Args, Debug, Default)]
+pub struct LegacyArgs {
+    /// Deprecated: use `wallpaper set PATH`
+    #[arg(hide = true)]
+    pub path: Option<PathBuf>,
+
+    /// Deprecated: use `wallpaper gui`
+    #[arg(long, hide = true, conflicts_with = "path")]
+    pub gui: bool,
+
+    /// Deprecated: use `wallpaper history`
+    #[arg(long, hide = true)]
     pub history: bool,
 
-    #[arg(long = "restore", value_name = "RESTORE", default_value = "0")]
-    pub restore_step: i32,
+    /// Deprecated: use `wallpaper restore N`
+    #[arg(long = "restore", value_name = "N", hide = true)]
+    pub restore_step: Option<i32>,
 
-    /// Dry run mode - show what would be executed without running commands
-    #[arg(long = "dry-run")]
-    pub dry_run: bool,
+    /// Deprecated: use `wallpaper set --output`
+    #[arg(long = "output", value_name = "NAME[=PATH]", hide = true)]
+    pub outputs: Vec<OutputArg>,
+
+    /// Deprecated: use `wallpaper set --primary`
+    #[arg(long, value_name = "NAME", hide = true, requires = "outputs")]
+    pub primary: Option<String>,
+}
+
+impl LegacyArgs {
+    fn is_used(&self) -> bool {
+        self.path.is_some()
+            || self.gui
+            || self.history
+            || self.restore_step.is_some()
+            || !self.outputs.is_empty()
+    }
+}
+
+impl Cli {
+    /// Resolve the command to run, translating deprecated flat options and
+    /// merging palette/pipeline options given before the subcommand
+    pub fn into_command(self) -> Result<Command> {
+        let Cli {
+            command,
+            palette,
+            pipeline,
+            legacy,
+            ..
+        } = self;
+
+        match command {
+            Some(_) if legacy.is_used() => Err(anyhow!(
+                "Deprecated flat options (PATH, --gui, --history, --restore, --output) cannot be combined with a subcommand."
+            )),
+            Some(command) => Ok(merge_outer_args(command, palette, pipeline)),
+            None => legacy_command(legacy, palette, pipeline),
+        }
+    }
+}
+
+fn merge_outer_args(command: Command, palette: PaletteArgs, pipeline: PipelineArgs) -> Command {
+    match command {
+        Command::Set(args) => Command::Set(SetArgs {
+            palette: args.palette.merge(palette),
+            pipeline: args.pipeline.merge(pipeline),
+            ..args
+        }),
+        Command::Gui(args) => Command::Gui(GuiArgs {
+            palette: args.palette.merge(palette),
+            pipeline: args.pipeline.merge(pipeline),
+        }),
+        Command::Random(args) => Command::Random(RandomArgs {
+            palette: args.palette.merge(palette),
+            pipeline: args.pipeline.merge(pipeline),
+            ..args
+        }),
+        Command::Daemon(args) => Command::Daemon(DaemonArgs {
+            palette: args.palette.merge(palette),
+            pipeline: args.pipeline.merge(pipeline),
+            ..args
+        }),
+        Command::Restore(args) => Command::Restore(RestoreArgs {
+            pipeline: args.pipeline.merge(pipeline),
+            ..args
+        }),
+        Command::Prev(args) => Command::Prev(NavigateArgs {
+            pipeline: args.pipeline.merge(pipeline),
+        }),
+        Command::Next(args) => Command::Next(NavigateArgs {
+            pipeline: args.pipeline.merge(pipeline),
+        }),
+        Command::BackToLatest(args) => Command::BackToLatest(NavigateArgs {
+            pipeline: args.pipeline.merge(pipeline),
+        }),
+        command => command,
+    }
+}
+
+fn legacy_command(
+    legacy: LegacyArgs,
+    palette: PaletteArgs,
+    pipeline: PipelineArgs,
+) -> Result<Command> {
+    let per_output = legacy.outputs.iter().any(|o| o.path.is_some());
+
+    // Validation: exactly one main option must be provided (dry-run is a modifier)
+    let main_options: Vec<&str> = [
+        (legacy.gui, "--gui"),
+        (legacy.path.is_some(), "PATH"),
+        (legacy.history, "--history"),
+        (legacy.restore_step.is_some(), "--restore"),
+        (per_output, "--output NAME=PATH"),
+    ]
+    .into_iter()
+    .filter_map(|(used, name)| used.then_some(name))
+    .collect();
+
+    match main_options.len() {
+        0 => {
+            return Err(anyhow!(
+                "No command given. Run `wallpaper --help` to see the available subcommands."
+            ));
+        }
+        1 => {}
+        _ => {
+            return Err(anyhow!(
+                "Options {} cannot be used together; give only one of them.",
+                main_options.join(", ")
+            ));
+        }
+    }
+
+    if legacy.history {
+        warn_deprecated("--history", "wallpaper history");
+        return Ok(Command::History(HistoryArgs::default()));
+    }
+
+    if let Some(step) = legacy.restore_step {
+        warn_deprecated("--restore N", "wallpaper restore N");
+        if step < 1 {
+            return Err(anyhow!("Restore step must be positive (1-based indexing)."));
+        }
+        return Ok(Command::Restore(RestoreArgs {
+            step,
+            favorite: None,
+            pipeline,
+        }));
+    }
+
+    if legacy.gui {
+        warn_deprecated("--gui", "wallpaper gui");
+        return Ok(Command::Gui(GuiArgs { palette, pipeline }));
+    }
+
+    warn_deprecated("wallpaper PATH", "wallpaper set PATH");
+    Ok(Command::Set(SetArgs {
+        path: legacy.path,
+        outputs: legacy.outputs,
+        primary: legacy.primary,
+        palette,
+        pipeline,
+    }))
+}
+
+fn warn_deprecated(old: &str, new: &str) {
+    warning!("`{}` is deprecated, use `{}` instead", old, new);
+}
+
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let interval = humantime::parse_duration(value).map_err(|e| e.to_string())?;
+    if interval < MIN_INTERVAL {
+        return Err(format!(
+            "must be at least {}",
+            humantime::format_duration(MIN_INTERVAL)
+        ));
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command> {
+        let cli = Cli::try_parse_from(std::iter::once("wallpaper").chain(args.iter().copied()))?;
+        cli.into_command()
+    }
+
+    #[test]
+    fn test_set_subcommand() {
+        match parse(&["set", "/test/image.jpg", "-l", "--type", "scheme-content"]).unwrap() {
+            Command::Set(args) => {
+                assert_eq!(args.path, Some(PathBuf::from("/test/image.jpg")));
+                assert!(args.palette.light);
+                assert_eq!(args.palette.matugen_type(), "scheme-content");
+            }
+            other => panic!("Expected Set, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_options_before_subcommand_are_merged() {
+        match parse(&["-l", "--backend", "feh", "set", "/test/image.jpg"]).unwrap() {
+            Command::Set(args) => {
+                assert!(args.palette.light);
+                assert_eq!(args.palette.matugen_type(), DEFAULT_MATUGEN_TYPE);
+                assert_eq!(args.pipeline.backend, Some(Backend::Feh));
+            }
+            other => panic!("Expected Set, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_daemon_interval() {
+        match parse(&["daemon", "~/walls", "--interval", "1h 30m"]).unwrap() {
+            Command::Daemon(args) => assert_eq!(args.interval, Duration::from_secs(90 * 60)),
+            other => panic!("Expected Daemon, got {:?}", other),
+        }
+        assert!(parse(&["daemon", "~/walls", "--interval", "0s"]).is_err());
+        assert!(parse(&["daemon", "~/walls", "--interval", "500ms"]).is_err());
+    }
+
+    #[test]
+    fn test_history_format() {
+        match parse(&["history", "--format", "ndjson"]).unwrap() {
+            Command::History(args) => assert_eq!(args.format, HistoryFormat::Ndjson),
+            other => panic!("Expected History, got {:?}", other),
+        }
+        assert!(parse(&["history", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_history_star() {
+        match parse(&["history", "star", "3"]).unwrap() {
+            Command::History(HistoryArgs {
+                action: Some(HistoryAction::Star(args)),
+                ..
+            }) => assert_eq!(args.step, 3),
+            other => panic!("Expected history star, got {:?}", other),
+        }
+        assert!(parse(&["history", "unstar", "0"]).is_err());
+        assert!(parse(&["history", "star"]).is_err());
+    }
+
+    #[test]
+    fn test_tag_options() {
+        match parse(&["history", "--tag", "Nature,dark", "--tag", "work"]).unwrap() {
+            Command::History(args) => {
+                assert_eq!(args.filter().unwrap().tags, vec!["nature", "dark", "work"])
+            }
+            other => panic!("Expected History, got {:?}", other),
+        }
+        match parse(&["random", "/walls", "--tag", "work"]).unwrap() {
+            Command::Random(args) => assert_eq!(args.tags, vec!["work"]),
+            other => panic!("Expected Random, got {:?}", other),
+        }
+        match parse(&["tag", "add", "/a.jpg", "dark", "nature"]).unwrap() {
+            Command::Tag(TagArgs {
+                action: TagAction::Add(args),
+            }) => assert_eq!(args.tags, vec!["dark", "nature"]),
+            other => panic!("Expected tag add, got {:?}", other),
+        }
+        assert!(parse(&["tag", "add", "/a.jpg"]).is_err());
+    }
+
+    #[test]
+    fn test_verbosity_flags() {
+        let cli = Cli::try_parse_from(["wallpaper", "-vv", "current"]).unwrap();
+        assert_eq!(cli.verbose, 2);
+        assert!(!cli.quiet);
+
+        let cli = Cli::try_parse_from(["wallpaper", "set", "/test/image.jpg", "--quiet"]).unwrap();
+        assert!(cli.quiet);
+        assert_eq!(cli.verbose, 0);
+
+        assert!(Cli::try_parse_from(["wallpaper", "-q", "-v", "current"]).is_err());
+    }
+
+    #[test]
+    fn test_navigation_commands() {
+        assert!(matches!(parse(&["prev"]).unwrap(), Command::Prev(_)));
+        assert!(matches!(parse(&["next"]).unwrap(), Command::Next(_)));
+        match parse(&["--backend", "swww", "back-to-latest"]).unwrap() {
+            Command::BackToLatest(args) => assert_eq!(args.pipeline.backend, Some(Backend::Swww)),
+            other => panic!("Expected BackToLatest, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_history_filters() {
+        let args = [
+            "history",
+            "--grep",
+            "forest",
+            "--type",
+            "scheme-fidelity",
+            "--light",
+            "--since",
+            "2d",
+            "--until",
+            "2026-10-01",
+        ];
+        match parse(&args).unwrap() {
+            Command::History(args) => {
+                let filter = args.filter().unwrap();
+                assert_eq!(filter.grep.as_deref(), Some("forest"));
+                assert_eq!(filter.matugen_type.as_deref(), Some("scheme-fidelity"));
+                assert_eq!(filter.is_light, Some(true));
+                assert!(filter.since.is_some());
+                assert_eq!(filter.until, Some(1_790_899_200));
+            }
+            other => panic!("Expected History, got {:?}", other),
+        }
+        assert!(parse(&["history", "--light", "--dark"]).is_err());
+        assert!(parse(&["history", "--since", "yesterday-ish"]).is_err());
+    }
+
+    #[test]
+    fn test_history_gc() {
+        assert!(matches!(
+            parse(&["history", "gc", "--dry-run"]).unwrap(),
+            Command::History(HistoryArgs {
+                action: Some(HistoryAction::Gc),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_restore_favorite() {
+        match parse(&["restore", "--favorite", "forest"]).unwrap() {
+            Command::Restore(args) => assert_eq!(args.favorite.as_deref(), Some("forest")),
+            other => panic!("Expected Restore, got {:?}", other),
+        }
+        assert!(parse(&["restore", "2", "--favorite", "forest"]).is_err());
+    }
+
+    #[test]
+    fn test_history_retention_options() {
+        let cli = Cli::try_parse_from([
+            "wallpaper",
+            "set",
+            "/test/image.jpg",
+            "--history-size",
+            "100",
+            "--history-max-age",
+            "90d",
+        ])
+        .unwrap();
+        assert_eq!(cli.history_size, Some(100));
+        assert_eq!(
+            cli.history_max_age,
+            Some(Duration::from_secs(90 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn test_restore_defaults_to_most_recent() {
+        match parse(&["restore"]).unwrap() {
+            Command::Restore(args) => assert_eq!(args.step, 1),
+            other => panic!("Expected Restore, got {:?}", other),
+        }
+        assert!(parse(&["restore", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_path() {
+        match parse(&["-l", "/test/image.jpg"]).unwrap() {
+            Command::Set(args) => {
+                assert_eq!(args.path, Some(PathBuf::from("/test/image.jpg")));
+                assert!(args.palette.light);
+            }
+            other => panic!("Expected Set, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_legacy_flags() {
+        assert!(matches!(
+            parse(&["--history"]).unwrap(),
+            Command::History(HistoryArgs {
+                action: None,
+                format: HistoryFormat::Table,
+                favorites: false,
+                ..
+            })
+        ));
+        assert!(matches!(parse(&["--gui"]).unwrap(), Command::Gui(_)));
+        match parse(&["--restore", "3"]).unwrap() {
+            Command::Restore(args) => assert_eq!(args.step, 3),
+            other => panic!("Expected Restore, got {:?}", other),
+        }
+        assert!(matches!(
+            parse(&["--output", "DP-1=/a.jpg"]).unwrap(),
+            Command::Set(_)
+        ));
+    }
+
+    #[test]
+    fn test_legacy_validation() {
+        let error = |args: &[&str]| parse(args).unwrap_err().to_string();
+
+        assert!(error(&[]).starts_with("No command given"));
+        assert_eq!(
+            error(&["--history", "--restore", "1"]),
+            "Options --history, --restore cannot be used together; give only one of them."
+        );
+        assert_eq!(
+            error(&["--restore", "2", "--output", "DP-1=/a.jpg"]),
+            "Options --restore, --output NAME=PATH cannot be used together; give only one of them."
+        );
+        assert!(parse(&["--restore", "0"]).is_err());
+        assert!(parse(&["--gui", "/test/image.jpg"]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_flags_conflict_with_subcommand() {
+        assert!(parse(&["--history", "current"]).is_err());
+    }
 }
@@ -0,0 +1,544 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+};
+
+use crate::backends::Backend;
+use crate::cli::{
+    Command, DaemonArgs, GuiArgs, HistoryAction, HistoryArgs, NavigateArgs, PaletteArgs,
+    PipelineArgs, RandomArgs, RestoreArgs, SetArgs, TagAction, TagArgs,
+};
+use crate::config::{Config, HistoryConfig};
+use crate::daemon::run_daemon;
+use crate::generators::{DEFAULT_GENERATORS, Generator};
+use crate::history::{
+    Navigation, WallpaperEntry, WallpaperHistory, add_to_history, display_history, gc_history,
+    move_cursor, navigation_target, restore_favorite, restore_from_history, star_entry,
+};
+use crate::images::Image;
+use crate::logging::{self, info};
+use crate::outputs::{check_outputs_exist, query_outputs};
+use crate::pipeline::{
+    PipelineReport, StepContext, apply_backend_only, apply_wallpaper,
+    check_custom_steps_take_outputs,
+};
+use crate::random::{ImageFilter, collect_images, new_rng, pick_image};
+use crate::tags::{TagStore, normalize_tags};
+use crate::utils::{expand_tilde, normalize_and_check_dir, normalize_and_check_path};
+
+/// Exit code when the wallpaper was set but an optional step failed or was skipped
+pub const PARTIAL_SUCCESS_EXIT_CODE: i32 = 3;
+
+/// State shared by every subcommand
+pub struct App {
+    pub version: &'static str,
+    pub config: Config,
+    pub dry_run: bool,
+    /// Set once any run left out or lost an optional step
+    pub partial: Cell<bool>,
+}
+
+impl App {
+    pub fn new(version: &'static str, config: Config, dry_run: bool) -> Self {
+        Self {
+            version,
+            config,
+            dry_run,
+            partial: Cell::new(false),
+        }
+    }
+
+    fn track(&self, report: PipelineReport) {
+        if report.is_partial() {
+            self.partial.set(true);
+        }
+    }
+
+    /// `--backend`/`--generator` win over config.toml, which wins over the defaults
+    fn programs<'a>(&'a self, pipeline: &'a PipelineArgs) -> (Backend, &'a [Generator]) {
+        let backend = pipeline.backend.or(self.config.backend).unwrap_or_default();
+
+        let generators = if !pipeline.generators.is_empty() {
+            pipeline.generators.as_slice()
+        } else {
+            self.config
+                .generators
+                .as_deref()
+                .unwrap_or(DEFAULT_GENERATORS)
+        };
+
+        (backend, generators)
+    }
+
+    /// Run the pipeline for one image
+    fn apply(
+        &self,
+        image: &Image,
+        palette: &PaletteArgs,
+        pipeline: &PipelineArgs,
+        outputs: &[String],
+    ) -> Result<()> {
+        let (backend, generators) = self.programs(pipeline);
+
+        let ctx = StepContext::new(
+            &image.path,
+            palette.matugen_type(),
+            palette.light,
+            backend,
+            generators,
+        )
+        .with_outputs(outputs)
+        .with_templates(&self.config.templates, self.config.template_colors);
+
+        let previous = self.previous_entry(outputs)?;
+        let rollback = previous.as_ref().map(|entry| {
+            StepContext::new(
+                &entry.path,
+                &entry.matugen_type,
+                entry.is_light,
+                backend,
+                generators,
+            )
+            .with_outputs(outputs)
+            .with_templates(&self.config.templates, self.config.template_colors)
+        });
+
+        let report = apply_wallpaper(&self.config, &ctx, rollback.as_ref(), self.dry_run)?;
+        self.track(report);
+        Ok(())
+    }
+
+    /// Only set the wallpaper on `outputs`, without generating a palette
+    fn apply_backend(
+        &self,
+        image: &Image,
+        palette: &PaletteArgs,
+        pipeline: &PipelineArgs,
+        outputs: &[String],
+    ) -> Result<()> {
+        let (backend, generators) = self.programs(pipeline);
+
+        let ctx = StepContext::new(
+            &image.path,
+            palette.matugen_type(),
+            palette.light,
+            backend,
+            generators,
+        )
+        .with_outputs(outputs);
+
+        let previous = self.previous_entry(outputs)?;
+        let rollback = previous.as_ref().map(|entry| StepContext {
+            path: &entry.path,
+            ..ctx
+        });
+
+        let report = apply_backend_only(&self.config, &ctx, rollback.as_ref(), self.dry_run)?;
+        self.track(report);
+        Ok(())
+    }
+
+    /// Wallpaper shown on `outputs` before this run, re-applied if the pipeline fails
+    fn previous_entry(&self, outputs: &[String]) -> Result<Option<WallpaperEntry>> {
+        if self.dry_run {
+            return Ok(None);
+        }
+        let history = WallpaperHistory::load(&self.config.history)?;
+        Ok(history
+            .on_screen(outputs)
+            .filter(|entry| !entry.is_missing())
+            .cloned())
+    }
+
+    /// Run the pipeline for one image and record it in history (only if not dry run)
+    fn apply_and_record(
+        &self,
+        image: &Image,
+        palette: &PaletteArgs,
+        pipeline: &PipelineArgs,
+        outputs: &[String],
+    ) -> Result<()> {
+        self.apply(image, palette, pipeline, outputs)?;
+
+        if !self.dry_run {
+            record(image, palette, outputs, &self.config.history)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add one history entry per output, or a single one when every output was targeted
+fn record(
+    image: &Image,
+    palette: &PaletteArgs,
+    outputs: &[String],
+    config: &HistoryConfig,
+) -> Result<()> {
+    let entry = |output: Option<String>| {
+        WallpaperEntry::new(
+            image.path.clone(),
+            palette.matugen_type().to_string(),
+            palette.light,
+        )
+        .with_output(output)
+        .with_image(image.info)
+    };
+
+    if outputs.is_empty() {
+        return add_to_history(entry(None), config);
+    }
+    for output in outputs {
+        add_to_history(entry(Some(output.clone())), config)?;
+    }
+    Ok(())
+}
+
+pub fn run(command: Command, app: &App) -> Result<()> {
+    match command {
+        Command::Set(args) => set(app, args),
+        Command::Gui(args) => gui(app, args),
+        Command::Random(args) => random(app, args),
+        Command::Daemon(args) => daemon(app, args),
+        Command::History(args) => history(app, args),
+        Command::Restore(args) => restore(app, args),
+        Command::Prev(args) => navigate(app, args, Navigation::Prev),
+        Command::Next(args) => navigate(app, args, Navigation::Next),
+        Command::BackToLatest(args) => navigate(app, args, Navigation::Latest),
+        Command::Current => current(app),
+        Command::Tag(args) => tag(args),
+    }
+}
+
+fn set(app: &App, args: SetArgs) -> Result<()> {
+    // `--output NAME=PATH` assigns an image per output, `--output NAME` targets PATH
+    let per_output: Vec<(&str, &Path)> = args
+        .outputs
+        .iter()
+        .filter_map(|o| Some((o.name.as_str(), o.path.as_deref()?)))
+        .collect();
+    let targeted: Vec<String> = args
+        .outputs
+        .iter()
+        .filter(|o| o.path.is_none())
+        .map(|o| o.name.clone())
+        .collect();
+
+    if !per_output.is_empty() && !targeted.is_empty() {
+        return Err(anyhow!(
+            "Cannot mix --output NAME with --output NAME=PATH in a single run."
+        ));
+    }
+    if !per_output.is_empty() && args.path.is_some() {
+        return Err(anyhow!(
+            "PATH cannot be combined with --output NAME=PATH. Assign every image with --output."
+        ));
+    }
+
+    if !args.outputs.is_empty() {
+        check_outputs_supported(app, &args.pipeline)?;
+        if !app.dry_run {
+            let available = query_outputs()?;
+            check_outputs_exist(args.outputs.iter().map(|o| o.name.as_str()), &available)?;
+        }
+    }
+
+    if !per_output.is_empty() {
+        return set_per_output(app, &args, &per_output);
+    }
+
+    let raw_path = args
+        .path
+        .as_deref()
+        .ok_or_else(|| anyhow!("No path provided. Supply a path."))?;
+    let image = Image::open(raw_path)?;
+
+    print_banner(app, &image);
+
+    app.apply_and_record(&image, &args.palette, &args.pipeline, &targeted)?;
+
+    info!("Done.");
+    Ok(())
+}
+
+fn set_per_output(app: &App, args: &SetArgs, per_output: &[(&str, &Path)]) -> Result<()> {
+    let primary = args.primary.as_deref().unwrap_or(per_output[0].0);
+    if !per_output.iter().any(|(name, _)| *name == primary) {
+        return Err(anyhow!(
+            "Primary output {} has no image assigned with --output {}=PATH",
+            primary,
+            primary
+        ));
+    }
+
+    // Check every image before changing any output
+    let mut assignments = Vec::with_capacity(per_output.len());
+    for (name, raw_path) in per_output {
+        assignments.push((name.to_string(), Image::open(raw_path)?));
+    }
+
+    // Secondary outputs only get their wallpaper; the primary one also drives the palette.
+    // Primary goes last so it also becomes the most recent history entry.
+    assignments.sort_by_key(|(name, _)| name == primary);
+
+    for (name, image) in &assignments {
+        info!(
+            "wallpaper {} - {}: {} ({})",
+            app.version,
+            name,
+            image.path.display(),
+            image.info
+        );
+
+        let outputs = [name.clone()];
+        if name == primary {
+            app.apply_and_record(image, &args.palette, &args.pipeline, &outputs)?;
+        } else {
+            app.apply_backend(image, &args.palette, &args.pipeline, &outputs)?;
+
+            if !app.dry_run {
+                record(image, &args.palette, &outputs, &app.config.history)?;
+            }
+        }
+    }
+
+    info!("Done.");
+    Ok(())
+}
+
+fn gui(app: &App, args: GuiArgs) -> Result<()> {
+    let raw_path = rfd::FileDialog::new()
+        .set_title("Choose wallpaper")
+        .pick_file()
+        .ok_or_else(|| anyhow!("No file selected via GUI"))?;
+
+    let image = Image::open(&raw_path)?;
+
+    print_banner(app, &image);
+
+    app.apply_and_record(&image, &args.palette, &args.pipeline, &[])?;
+
+    info!("Done.");
+    Ok(())
+}
+
+fn random(app: &App, args: RandomArgs) -> Result<()> {
+    let raw_path = pick_random_wallpaper(app, &args)?;
+    let image = Image::open(&raw_path)?;
+
+    print_banner(app, &image);
+
+    app.apply_and_record(&image, &args.palette, &args.pipeline, &[])?;
+
+    info!("Done.");
+    Ok(())
+}
+
+fn pick_random_wallpaper(app: &App, args: &RandomArgs) -> Result<PathBuf> {
+    let dir = normalize_and_check_dir(&args.dir)?;
+    let filter = ImageFilter::new(&args.extensions, args.glob.as_deref())?;
+
+    let mut candidates = collect_images(&dir, &filter)?;
+
+    if !args.tags.is_empty() {
+        let tags = normalize_tags(&args.tags)?;
+        let store = TagStore::load()?;
+        candidates.retain(|c| store.has_all(c, &tags));
+        if candidates.is_empty() {
+            return Err(anyhow!(
+                "No images in {} are tagged {}",
+                dir.display(),
+                tags.join(", ")
+            ));
+        }
+    }
+    let recent = WallpaperHistory::load(&app.config.history)?.recent_paths(args.avoid);
+    let mut rng = new_rng(args.seed);
+
+    let picked = pick_image(&candidates, &recent, &mut rng)
+        .with_context(|| format!("Nothing to pick from in {}", dir.display()))?;
+
+    info!(
+        "Picked {} ({} candidates)",
+        picked.display(),
+        candidates.len()
+    );
+
+    Ok(picked.clone())
+}
+
+fn daemon(app: &App, args: DaemonArgs) -> Result<()> {
+    // Usually given as '~/walls' in a service file, so the shell did not expand it
+    let source = expand_tilde(&args.source);
+    let source = if source.is_dir() {
+        normalize_and_check_dir(&source)?
+    } else {
+        normalize_and_check_path(&source)?
+    };
+
+    run_daemon(&source, args.interval, app.dry_run, |raw_path| {
+        let image = Image::open(raw_path)?;
+        print_banner(app, &image);
+
+        app.apply_and_record(&image, &args.palette, &args.pipeline, &[])
+    })
+}
+
+fn history(app: &App, args: HistoryArgs) -> Result<()> {
+    let config = &app.config.history;
+    match &args.action {
+        Some(HistoryAction::Star(star)) => star_entry(star.step as usize, true, config),
+        Some(HistoryAction::Unstar(star)) => star_entry(star.step as usize, false, config),
+        Some(HistoryAction::Gc) => gc_history(&app.config.history, app.dry_run),
+        None => display_history(args.format, &args.filter()?, config),
+    }
+}
+
+fn restore(app: &App, args: RestoreArgs) -> Result<()> {
+    let entry = match &args.favorite {
+        Some(name) => {
+            let entry = restore_favorite(name, &app.config.history)?;
+            info!("Restoring favorite wallpaper: {}", entry.path.display());
+            entry
+        }
+        None => {
+            let entry = restore_from_history(args.step, &app.config.history)?;
+            info!(
+                "Restoring wallpaper from step {}: {}",
+                args.step,
+                entry.path.display()
+            );
+            entry
+        }
+    };
+
+    let (image, palette, outputs) = reapply_settings(app, &entry, &args.pipeline)?;
+
+    // Add restored wallpaper to history as most recent
+    app.apply_and_record(&image, &palette, &args.pipeline, &outputs)?;
+
+    info!("Wallpaper restored successfully.");
+    Ok(())
+}
+
+/// Apply another history entry without reordering history; only the cursor moves
+fn navigate(app: &App, args: NavigateArgs, step: Navigation) -> Result<()> {
+    let (index, entry) = navigation_target(step, &app.config.history)?;
+    info!(
+        "Going to history step {}: {}",
+        index + 1,
+        entry.path.display()
+    );
+
+    let (image, palette, outputs) = reapply_settings(app, &entry, &args.pipeline)?;
+    app.apply(&image, &palette, &args.pipeline, &outputs)?;
+
+    if !app.dry_run {
+        move_cursor(&entry, &app.config.history)?;
+    }
+
+    info!("Done.");
+    Ok(())
+}
+
+/// Image, palette and outputs to apply `entry` again the way it was set
+fn reapply_settings(
+    app: &App,
+    entry: &WallpaperEntry,
+    pipeline: &PipelineArgs,
+) -> Result<(Image, PaletteArgs, Vec<String>)> {
+    let image = Image::open(&entry.path)?;
+
+    // Put the image back on the output it was originally set on
+    let outputs: Vec<String> = entry.output.iter().cloned().collect();
+    if !outputs.is_empty() {
+        check_outputs_supported(app, pipeline)?;
+    }
+
+    let palette = PaletteArgs {
+        light: entry.is_light,
+        matugen_type: Some(entry.matugen_type.clone()),
+    };
+    Ok((image, palette, outputs))
+}
+
+fn current(app: &App) -> Result<()> {
+    let history = WallpaperHistory::load(&app.config.history)?;
+    let entry = history
+        .current()
+        .ok_or_else(|| anyhow!("No wallpaper history found."))?;
+
+    println!("{}", entry.path.display());
+    Ok(())
+}
+
+/// Key of `path` in tags.json. The image may be gone already; its tags can still
+/// be listed and removed.
+fn tagged_path(path: &Path) -> Result<PathBuf> {
+    normalize_and_check_path(path).or_else(|_| {
+        let path = expand_tilde(path);
+        std::path::absolute(&path)
+            .with_context(|| format!("Failed to resolve path: {}", path.display()))
+    })
+}
+
+fn tag(args: TagArgs) -> Result<()> {
+    match args.action {
+        TagAction::Add(edit) => {
+            let image = Image::open(&edit.path)?;
+            let store = TagStore::update(|store| store.add(&image.path, &edit.tags))?;
+            print_tags(&image.path, &store);
+        }
+        TagAction::Remove(edit) => {
+            let path = tagged_path(&edit.path)?;
+            let store = TagStore::update(|store| store.remove(&path, &edit.tags))?;
+            print_tags(&path, &store);
+        }
+        TagAction::List { path: Some(path) } => {
+            let path = tagged_path(&path)?;
+            print_tags(&path, &TagStore::load()?);
+        }
+        TagAction::List { path: None } => {
+            for (tag, count) in TagStore::load()?.counts() {
+                println!("{} ({})", tag, count);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_tags(path: &Path, store: &TagStore) {
+    let tags: Vec<&str> = store
+        .get(path)
+        .map(|t| t.iter().map(String::as_str).collect())
+        .unwrap_or_default();
+
+    if tags.is_empty() {
+        println!("{}: no tags", path.display());
+    } else {
+        println!("{}: {}", path.display(), tags.join(", "));
+    }
+}
+
+fn print_banner(app: &App, image: &Image) {
+    info!(
+        "wallpaper {} - {} ({})",
+        app.version,
+        image.path.display(),
+        image.info
+    );
+    logging::record(&format!("wallpaper: {}", image.path.display()));
+}
+
+/// The backend and every custom step must be able to target individual outputs
+fn check_outputs_supported(app: &App, pipeline: &PipelineArgs) -> Result<()> {
+    let (backend, _) = app.programs(pipeline);
+    if !backend.supports_outputs() {
+        return Err(anyhow!(
+            "Backend {} cannot target individual outputs. Use --backend swww.",
+            backend.name()
+        ));
+    }
+    check_custom_steps_take_outputs(&app.config)
+}
@@ -0,0 +1,393 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Deserializer};
+use std::{fs, path::PathBuf, time::Duration};
+
+use crate::backends::Backend;
+use crate::generators::Generator;
+use crate::history::{MAX_HISTORY_ENTRIES, Retention};
+use crate::pipeline::StepPolicy;
+
+const CONFIG_FILENAME: &str = "config.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Wallpaper setter used by the `backend` step. `--backend` takes precedence.
+    pub backend: Option<Backend>,
+
+    /// Palette generators used by the `generators` step. `--generator` takes precedence.
+    pub generators: Option<Vec<Generator>>,
+
+    /// Pipeline steps in execution order. Empty means the built-in pipeline.
+    pub pipeline: Vec<StepConfig>,
+
+    /// `[history]` table
+    pub history: HistoryConfig,
+
+    /// `[log]` table
+    pub log: LogConfig,
+
+    /// `[[templates]]` entries rendered with the palette after the generators ran
+    pub templates: Vec<TemplateConfig>,
+
+    /// Palette generator whose colors fill the templates. Defaults to the first
+    /// generator in the pipeline; "native" extracts them from the wallpaper.
+    pub template_colors: Option<Generator>,
+
+    /// Time limit for every pipeline step, e.g. "20s". Defaults to 15s for
+    /// wallpaper setters and 60s for everything else.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub step_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Hide entries whose image file no longer exists when history is read, and
+    /// drop them from history.json with the next change
+    pub auto_prune: bool,
+
+    /// Number of entries to keep, 0 means unlimited. `--history-size` takes precedence.
+    pub max_entries: usize,
+
+    /// Drop entries older than this, e.g. "90d". `--history-max-age` takes precedence.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_age: Option<Duration>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            auto_prune: false,
+            max_entries: MAX_HISTORY_ENTRIES,
+            max_age: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Record every run in `wallpaper.log` in the data directory
+    pub enabled: bool,
+
+    /// Size in bytes after which the log is rotated, 0 means never
+    pub max_size: u64,
+
+    /// Number of rotated logs (wallpaper.log.1, .2, ...) to keep
+    pub keep: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_size: 1024 * 1024,
+            keep: 3,
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_entries: self.max_entries,
+            max_age: self.max_age,
+        }
+    }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    humantime::parse_duration(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StepConfig {
+    /// Step name, either a built-in step (backend, generators, swww, matugen, wallust,
+    /// pywal, pywal16, hellwal, native) or any label
+    pub name: String,
+
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Executable to run. Defaults to `name`.
+    pub program: Option<String>,
+
+    /// Argument templates. Supports {path}, {type} and {light} placeholders.
+    pub args: Option<Vec<String>>,
+
+    /// Extra argument templates appended only in light mode
+    #[serde(default)]
+    pub light_args: Vec<String>,
+
+    /// Run at the same time as neighbouring parallel steps. Defaults to true for
+    /// palette generators and false for everything else.
+    pub parallel: Option<bool>,
+
+    /// "required" (default), "optional" or "skip-if-missing"
+    #[serde(default)]
+    pub policy: StepPolicy,
+
+    /// Time limit for this step, e.g. "5s". Overrides `step_timeout`.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+}
+
+/// A file rendered from a template, e.g. a terminal color scheme
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    /// Template file with `{{color0}}`, `{{background}}`, `{{accent | lighten(10)}}`, ...
+    pub input: PathBuf,
+
+    /// Where the rendered file is written, replacing it atomically
+    pub output: PathBuf,
+}
+
+impl StepConfig {
+    /// Steps without `program` and `args` are built-in steps
+    pub fn is_custom(&self) -> bool {
+        self.program.is_some() || self.args.is_some()
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let config_path = get_config_file_path()?;
+
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+
+        Self::from_toml_str(&content)
+            .with_context(|| format!("Failed to parse config file: {}", config_path.display()))
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<Self> {
+        let config: Config = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Settings serde accepts but that would be silently ignored
+    fn validate(&self) -> Result<()> {
+        if let Some(step) = self
+            .pipeline
+            .iter()
+            .find(|step| !step.is_custom() && !step.light_args.is_empty())
+        {
+            return Err(anyhow!(
+                "Step `{}`: `light_args` only apply to custom steps with `program` or `args`; built-in steps add their own light mode arguments",
+                step.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn get_config_file_path() -> Result<PathBuf> {
+    let config_dir = get_config_directory()?;
+    Ok(config_dir.join(CONFIG_FILENAME))
+}
+
+fn get_config_directory() -> Result<PathBuf> {
+    // Try to use XDG_CONFIG_HOME first, fallback to ~/.config
+    if let Ok(xdg_config_home) = std::env::var("XDG_CONFIG_HOME") {
+        Ok(PathBuf::from(xdg_config_home).join("wallpaper"))
+    } else if let Ok(home) = std::env::var("HOME") {
+        Ok(PathBuf::from(home).join(".config/wallpaper"))
+    } else {
+        Err(anyhow!(
+            "Unable to determine config directory. HOME environment variable not set."
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let config = Config::from_toml_str("").unwrap();
+        assert!(config.pipeline.is_empty());
+        assert!(config.backend.is_none());
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let config = Config::from_toml_str("").unwrap();
+        assert!(!config.history.auto_prune);
+
+        let config = Config::from_toml_str("[history]\nauto_prune = true").unwrap();
+        assert!(config.history.auto_prune);
+
+        assert!(Config::from_toml_str("[history]\nprune = true").is_err());
+    }
+
+    #[test]
+    fn test_parse_history_retention() {
+        let config = Config::from_toml_str("").unwrap();
+        assert_eq!(config.history.retention(), Retention::default());
+
+        let config =
+            Config::from_toml_str("[history]\nmax_entries = 200\nmax_age = \"90d\"").unwrap();
+        assert_eq!(
+            config.history.retention(),
+            Retention {
+                max_entries: 200,
+                max_age: Some(Duration::from_secs(90 * 24 * 60 * 60)),
+            }
+        );
+
+        assert!(Config::from_toml_str("[history]\nmax_age = \"soon\"").is_err());
+        assert!(Config::from_toml_str("[history]\nmax_entries = -1").is_err());
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let config = Config::from_toml_str("").unwrap();
+        assert!(config.log.enabled);
+        assert_eq!(config.log.max_size, 1024 * 1024);
+        assert_eq!(config.log.keep, 3);
+
+        let config =
+            Config::from_toml_str("[log]\nenabled = false\nmax_size = 4096\nkeep = 0").unwrap();
+        assert!(!config.log.enabled);
+        assert_eq!(config.log.max_size, 4096);
+        assert_eq!(config.log.keep, 0);
+
+        assert!(Config::from_toml_str("[log]\npath = \"/tmp/log\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        let config = Config::from_toml_str(r#"backend = "hyprpaper""#).unwrap();
+        assert_eq!(config.backend, Some(Backend::Hyprpaper));
+
+        assert!(Config::from_toml_str(r#"backend = "nitrogen""#).is_err());
+    }
+
+    #[test]
+    fn test_parse_generators() {
+        let config = Config::from_toml_str(r#"generators = ["pywal", "matugen"]"#).unwrap();
+        assert_eq!(
+            config.generators,
+            Some(vec![Generator::Pywal, Generator::Matugen])
+        );
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "swww"
+
+            [[pipeline]]
+            name = "wallust"
+            enabled = false
+
+            [[pipeline]]
+            name = "notify"
+            program = "notify-send"
+            args = ["Wallpaper", "{path}"]
+            light_args = ["--urgency", "low"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.pipeline.len(), 3);
+        assert_eq!(config.pipeline[0].name, "swww");
+        assert!(config.pipeline[0].enabled);
+        assert!(config.pipeline[0].program.is_none());
+        assert!(!config.pipeline[1].enabled);
+        assert_eq!(config.pipeline[2].program.as_deref(), Some("notify-send"));
+        assert_eq!(config.pipeline[2].args.as_ref().unwrap().len(), 2);
+        assert_eq!(config.pipeline[2].light_args, vec!["--urgency", "low"]);
+    }
+
+    #[test]
+    fn test_parse_templates() {
+        let config = Config::from_toml_str("").unwrap();
+        assert!(config.templates.is_empty());
+
+        let config = Config::from_toml_str(
+            r#"
+            [[templates]]
+            input = "~/.config/wallpaper/templates/kitty.conf"
+            output = "~/.config/kitty/colors.conf"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.templates.len(), 1);
+        assert_eq!(
+            config.templates[0].output,
+            PathBuf::from("~/.config/kitty/colors.conf")
+        );
+
+        assert!(Config::from_toml_str("[[templates]]\ninput = \"a\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_template_colors() {
+        let config = Config::from_toml_str("").unwrap();
+        assert!(config.template_colors.is_none());
+
+        let config = Config::from_toml_str(r#"template_colors = "wallust""#).unwrap();
+        assert_eq!(config.template_colors, Some(Generator::Wallust));
+
+        assert!(Config::from_toml_str(r#"template_colors = "kitty""#).is_err());
+    }
+
+    #[test]
+    fn test_light_args_need_custom_step() {
+        let error = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "matugen"
+            light_args = ["--mode", "light"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("Step `matugen`"), "{}", error);
+
+        assert!(
+            Config::from_toml_str(
+                r#"
+                [[pipeline]]
+                name = "matugen"
+                program = "matugen"
+                light_args = ["--mode", "light"]
+                "#,
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        let result = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "swww"
+            argz = ["img"]
+            "#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::history::get_data_directory;
+use crate::logging::{info, warning};
+use crate::random::{ImageFilter, collect_images};
+use crate::signals;
+use crate::utils::write_atomic;
+
+const DAEMON_STATE_FILENAME: &str = "daemon.json";
+
+/// Rotation position, saved after every change so a restart continues where it stopped
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DaemonState {
+    pub source: PathBuf,
+    pub position: usize,
+    pub current: Option<PathBuf>,
+}
+
+impl DaemonState {
+    pub fn load() -> Result<Self> {
+        let state_path = get_state_file_path()?;
+
+        if !state_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&state_path).with_context(|| {
+            format!("Failed to read daemon state file: {}", state_path.display())
+        })?;
+
+        serde_json::from_str(&content).with_context(|| "Failed to parse daemon state file")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let state_path = get_state_file_path()?;
+
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create data directory: {}", parent.display())
+            })?;
+        }
+
+        let content = serde_json::to_string_pretty(self)
+            .with_context(|| "Failed to serialize daemon state")?;
+
+        write_atomic(&state_path, content.as_bytes()).with_context(|| {
+            format!(
+                "Failed to write daemon state file: {}",
+                state_path.display()
+            )
+        })
+    }
+
+    /// Index of the next wallpaper in `playlist`. Follows the current image if
+    /// the playlist changed around it, and starts over for a different source.
+    pub fn next_index(&self, source: &Path, playlist: &[PathBuf]) -> usize {
+        if playlist.is_empty() || self.source != source {
+            return 0;
+        }
+
+        match self
+            .current
+            .as_ref()
+            .and_then(|c| playlist.iter().position(|p| p == c))
+        {
+            Some(index) => (index + 1) % playlist.len(),
+            // The current image was removed; whatever took its place comes next
+            None => self.position % playlist.len(),
+        }
+    }
+}
+
+fn get_state_file_path() -> Result<PathBuf> {
+    let data_dir = get_data_directory()?;
+    Ok(data_dir.join(DAEMON_STATE_FILENAME))
+}
+
+/// Images to rotate through: every image in a directory, or the lines of a playlist file
+pub fn load_playlist(source: &Path) -> Result<Vec<PathBuf>> {
+    if source.is_dir() {
+        let filter = ImageFilter::new(&[], None)?;
+        return collect_images(source, &filter);
+    }
+
+    let content = fs::read_to_string(source)
+        .with_context(|| format!("Failed to read playlist: {}", source.display()))?;
+
+    Ok(parse_playlist(
+        &content,
+        source.parent().unwrap_or(Path::new("/")),
+    ))
+}
+
+/// One path per line; blank lines and `#` comments are skipped and
+/// relative paths are resolved against the playlist's directory
+pub fn parse_playlist(content: &str, base_dir: &Path) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base_dir.join(shellexpand::tilde(line).as_ref()))
+        .collect()
+}
+
+/// Rotate wallpapers from `source` forever, calling `apply` for every change.
+/// A failed change is reported and skipped so one bad image does not stop the slideshow.
+/// In dry-run mode the rotation position is kept in memory only.
+pub fn run_daemon(
+    source: &Path,
+    interval: Duration,
+    dry_run: bool,
+    mut apply: impl FnMut(&Path) -> Result<()>,
+) -> Result<()> {
+    let mut state = DaemonState::load()?;
+
+    info!(
+        "Slideshow from {} every {}",
+        source.display(),
+        humantime::format_duration(interval)
+    );
+
+    loop {
+        let playlist = load_playlist(source)?;
+        if playlist.is_empty() {
+            return Err(anyhow!("No images found in {}", source.display()));
+        }
+
+        let index = state.next_index(source, &playlist);
+        let path = &playlist[index];
+
+        match apply(path) {
+            Ok(()) => {}
+            // Being stopped is how a slideshow ends, not an error
+            Err(e) if signals::received().is_some() => {
+                warning!("Stopped while setting {}: {:#}", path.display(), e);
+                info!("Slideshow stopped.");
+                return Ok(());
+            }
+            Err(e) => warning!("Failed to set {}: {:#}", path.display(), e),
+        }
+
+        state = DaemonState {
+            source: source.to_path_buf(),
+            position: index,
+            current: Some(path.clone()),
+        };
+        if !dry_run {
+            state.save()?;
+        }
+
+        if !signals::sleep(interval) {
+            info!("Slideshow stopped.");
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|n| PathBuf::from(format!("/w/{}", n)))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_playlist() {
+        let content = "\
+# morning
+a.jpg
+
+/abs/b.png
+  nested/c.jpg  
+";
+        assert_eq!(
+            parse_playlist(content, Path::new("/lists")),
+            vec![
+                PathBuf::from("/lists/a.jpg"),
+                PathBuf::from("/abs/b.png"),
+                PathBuf::from("/lists/nested/c.jpg")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_index_fresh_start() {
+        let state = DaemonState::default();
+        assert_eq!(state.next_index(Path::new("/w"), &playlist(&["a", "b"])), 0);
+    }
+
+    #[test]
+    fn test_next_index_resumes_after_current() {
+        let state = DaemonState {
+            source: PathBuf::from("/w"),
+            position: 1,
+            current: Some(PathBuf::from("/w/b")),
+        };
+
+        assert_eq!(
+            state.next_index(Path::new("/w"), &playlist(&["a", "b", "c"])),
+            2
+        );
+        // New file sorted before the current one shifts the index
+        assert_eq!(
+            state.next_index(Path::new("/w"), &playlist(&["0", "a", "b", "c"])),
+            3
+        );
+        // Wraps around at the end
+        assert_eq!(state.next_index(Path::new("/w"), &playlist(&["a", "b"])), 0);
+    }
+
+    #[test]
+    fn test_next_index_current_removed() {
+        let state = DaemonState {
+            source: PathBuf::from("/w"),
+            position: 1,
+            current: Some(PathBuf::from("/w/b")),
+        };
+
+        assert_eq!(
+            state.next_index(Path::new("/w"), &playlist(&["a", "c", "d"])),
+            1
+        );
+    }
+
+    #[test]
+    fn test_next_index_other_source() {
+        let state = DaemonState {
+            source: PathBuf::from("/other"),
+            position: 1,
+            current: Some(PathBuf::from("/w/b")),
+        };
+
+        assert_eq!(
+            state.next_index(Path::new("/w"), &playlist(&["a", "b", "c"])),
+            0
+        );
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let state = DaemonState {
+            source: PathBuf::from("/w"),
+            position: 3,
+            current: Some(PathBuf::from("/w/d.jpg")),
+        };
+
+        let serialized = serde_json::to_string(&state).unwrap();
+        let deserialized: DaemonState = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(deserialized, state);
+    }
+}
@@ -0,0 +1,640 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::generators::Generator;
+use crate::palette::{
+    self, Cluster, Color, Mode, PALETTE_FILENAME, Palette, Scheme, derive_scheme, extract_clusters,
+};
+use crate::utils::write_atomic;
+
+/// Directory in wallpaper's cache with one file per generator that ran
+const RUNS_DIRECTORY: &str = "generated";
+/// File systems may round modification times; how far off one may be from the run
+const MTIME_SLACK: Duration = Duration::from_secs(2);
+
+/// A successful generator run. The generators' own files do not say which
+/// wallpaper or mode they belong to, so the `templates` step checks this instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneratorRun {
+    pub wallpaper: PathBuf,
+    pub mode: Mode,
+    /// Milliseconds since the Unix epoch
+    pub started: u64,
+    pub finished: u64,
+    /// The colors printed by generators that write no file (matugen's JSON)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+}
+
+impl GeneratorRun {
+    pub fn new(
+        wallpaper: &Path,
+        is_light: bool,
+        started: SystemTime,
+        stdout: Option<String>,
+    ) -> Self {
+        Self {
+            wallpaper: wallpaper.to_path_buf(),
+            mode: Mode::from_light(is_light),
+            started: unix_millis(started),
+            finished: unix_millis(SystemTime::now()),
+            stdout,
+        }
+    }
+
+    /// Content of `path`, which must have been written by this run
+    fn read(&self, path: &Path, generator: Generator) -> Result<String> {
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let at = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+
+        if modified + MTIME_SLACK < at(self.started) {
+            return Err(anyhow!(
+                "{} was not updated when `{}` ran for {}. Is `{}` set up to write it?",
+                path.display(),
+                generator.name(),
+                self.wallpaper.display(),
+                generator.name()
+            ));
+        }
+        if modified > at(self.finished) + MTIME_SLACK {
+            return Err(anyhow!(
+                "{} changed after `{}` ran for {}, so it may hold other colors",
+                path.display(),
+                generator.name(),
+                self.wallpaper.display()
+            ));
+        }
+
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+}
+
+/// Remember that `generator` just produced the colors of `run.wallpaper`
+pub fn record(generator: Generator, run: &GeneratorRun) -> Result<()> {
+    write_run(&palette::get_cache_directory()?, generator, run)
+}
+
+fn write_run(cache_dir: &Path, generator: Generator, run: &GeneratorRun) -> Result<()> {
+    let dir = cache_dir.join(RUNS_DIRECTORY);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    let json = serde_json::to_string_pretty(run).context("Failed to serialize generator run")?;
+    write_atomic(&run_path(cache_dir, generator), json.as_bytes())
+}
+
+fn run_path(cache_dir: &Path, generator: Generator) -> PathBuf {
+    cache_dir
+        .join(RUNS_DIRECTORY)
+        .join(format!("{}.json", generator.name()))
+}
+
+/// Colors of `wallpaper` in the mode chosen with `--light`, as produced by
+/// `generator`, with a description of where they were read from. `native`
+/// extracts them when its palette.json belongs to another wallpaper; every other
+/// generator must have run for this wallpaper and mode.
+pub fn scheme_for(
+    wallpaper: &Path,
+    is_light: bool,
+    generator: Generator,
+) -> Result<(Scheme, String)> {
+    scheme_in(&palette::get_cache_home()?, wallpaper, is_light, generator)
+}
+
+fn scheme_in(
+    cache_home: &Path,
+    wallpaper: &Path,
+    is_light: bool,
+    generator: Generator,
+) -> Result<(Scheme, String)> {
+    let mode = Mode::from_light(is_light);
+    let cache_dir = cache_home.join("wallpaper");
+    let run = || checked_run(&cache_dir, generator, wallpaper, mode);
+
+    let (scheme, origin) = match generator {
+        Generator::Native => return native_scheme(&cache_dir, wallpaper, is_light),
+        Generator::Matugen => {
+            let stdout = run()?
+                .stdout
+                .ok_or_else(|| anyhow!("`matugen` printed no colors"))?;
+            (
+                matugen_scheme(&stdout, mode)?,
+                "its JSON output".to_string(),
+            )
+        }
+        Generator::Wallust => {
+            let path = cache_home.join("wallust/sequences");
+            let content = run()?.read(&path, generator)?;
+            (sequences_scheme(&content)?, path.display().to_string())
+        }
+        Generator::Pywal | Generator::Pywal16 | Generator::Hellwal => {
+            let dir = match generator {
+                Generator::Hellwal => "hellwal",
+                _ => "wal",
+            };
+            let path = cache_home.join(dir).join("colors.json");
+            let content = run()?.read(&path, generator)?;
+            let colors: PywalColors =
+                serde_json::from_str(&content).context("Not a pywal colors.json")?;
+            if let Some(other) = colors.wallpaper.as_ref().filter(|w| *w != wallpaper) {
+                return Err(anyhow!(
+                    "{} holds the colors of {}, not {}",
+                    path.display(),
+                    other.display(),
+                    wallpaper.display()
+                ));
+            }
+            (colors.into_scheme()?, path.display().to_string())
+        }
+    };
+    Ok((scheme, format!("`{}` ({})", generator.name(), origin)))
+}
+
+/// palette.json when it belongs to `wallpaper`, otherwise extracted on the spot
+fn native_scheme(cache_dir: &Path, wallpaper: &Path, is_light: bool) -> Result<(Scheme, String)> {
+    let path = cache_dir.join(PALETTE_FILENAME);
+    if let Some(mut palette) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Palette>(&content).ok())
+        .filter(|palette| palette.wallpaper == wallpaper)
+    {
+        palette.mode = Mode::from_light(is_light);
+        return Ok((
+            palette.active().clone(),
+            format!("`native` ({})", path.display()),
+        ));
+    }
+
+    let clusters = extract_clusters(wallpaper)?;
+    let palette = Palette::from_clusters(wallpaper, &clusters, is_light);
+    Ok((
+        palette.active().clone(),
+        "`native` (extracted from the wallpaper)".to_string(),
+    ))
+}
+
+/// The last run of `generator`, which must have been for `wallpaper` in `mode`
+fn checked_run(
+    cache_dir: &Path,
+    generator: Generator,
+    wallpaper: &Path,
+    mode: Mode,
+) -> Result<GeneratorRun> {
+    let run: GeneratorRun = fs::read_to_string(run_path(cache_dir, generator))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "`{}` has not generated any colors for wallpaper to use yet",
+                generator.name()
+            )
+        })?;
+
+    if run.wallpaper != wallpaper || run.mode != mode {
+        return Err(anyhow!(
+            "`{}` last generated colors for {} in {} mode, not for {} in {} mode. Did its step fail or get skipped?",
+            generator.name(),
+            run.wallpaper.display(),
+            run.mode.as_str(),
+            wallpaper.display(),
+            mode.as_str()
+        ));
+    }
+    Ok(run)
+}
+
+/// pywal's `colors.json`, also written by hellwal's `colors.json` template
+#[derive(Deserialize)]
+struct PywalColors {
+    wallpaper: Option<PathBuf>,
+    special: PywalSpecial,
+    colors: BTreeMap<String, Color>,
+}
+
+#[derive(Deserialize)]
+struct PywalSpecial {
+    background: Color,
+    foreground: Color,
+    cursor: Color,
+}
+
+impl PywalColors {
+    fn into_scheme(self) -> Result<Scheme> {
+        let mut colors = [self.special.background; 16];
+        for (index, color) in colors.iter_mut().enumerate() {
+            let key = format!("color{}", index);
+            *color = *self
+                .colors
+                .get(&key)
+                .ok_or_else(|| anyhow!("`{}` is missing", key))?;
+        }
+        Ok(terminal_scheme(
+            self.special.background,
+            self.special.foreground,
+            self.special.cursor,
+            colors,
+        ))
+    }
+}
+
+/// Terminal palettes have no accent; take the most colorful ANSI color
+fn terminal_scheme(
+    background: Color,
+    foreground: Color,
+    cursor: Color,
+    colors: [Color; 16],
+) -> Scheme {
+    let accent = colors[1..7]
+        .iter()
+        .copied()
+        .max_by(|x, y| x.to_oklab().chroma().total_cmp(&y.to_oklab().chroma()))
+        .unwrap_or(cursor);
+
+    Scheme {
+        background,
+        foreground,
+        cursor,
+        accent,
+        colors,
+    }
+}
+
+/// The escape sequences wallust saves for new terminals: `ESC ]4;N;#rrggbb` for
+/// the 16 colors and `ESC ]10`, `]11`, `]12` for foreground, background and cursor
+fn sequences_scheme(content: &str) -> Result<Scheme> {
+    let mut colors = [None; 16];
+    let (mut foreground, mut background, mut cursor) = (None, None, None);
+
+    for sequence in content.split("\x1b]").skip(1) {
+        let body = sequence.split(['\x07', '\x1b']).next().unwrap_or_default();
+        // Some terminals get the background as `[alpha]#rrggbb`
+        let color = |value: &str| value.rfind('#').and_then(|i| Color::from_hex(&value[i..]));
+
+        match body.split_once(';') {
+            Some(("4", rest)) => {
+                if let Some((index, value)) = rest.split_once(';')
+                    && let Ok(index) = index.parse::<usize>()
+                    && index < 16
+                {
+                    colors[index] = color(value);
+                }
+            }
+            Some(("10", value)) => foreground = color(value),
+            Some(("11", value)) => background = color(value),
+            Some(("12", value)) => cursor = color(value),
+            _ => {}
+        }
+    }
+
+    let mut found = [Color::new(0, 0, 0); 16];
+    for (index, (slot, color)) in found.iter_mut().zip(colors).enumerate() {
+        *slot = color.ok_or_else(|| anyhow!("color{} is missing", index))?;
+    }
+    let foreground = foreground.ok_or_else(|| anyhow!("the foreground color is missing"))?;
+    let background = background.ok_or_else(|| anyhow!("the background color is missing"))?;
+
+    Ok(terminal_scheme(
+        background,
+        foreground,
+        cursor.unwrap_or(foreground),
+        found,
+    ))
+}
+
+/// matugen's `--json hex` output. Material colors have no ANSI counterparts, so
+/// color1-6 and color9-14 take the hues of primary, secondary, tertiary and error.
+fn matugen_scheme(stdout: &str, mode: Mode) -> Result<Scheme> {
+    let json = stdout
+        .find('{')
+        .zip(stdout.rfind('}'))
+        .map(|(start, end)| &stdout[start..=end])
+        .ok_or_else(|| anyhow!("`matugen` printed no JSON"))?;
+    let json: Value = serde_json::from_str(json).context("Failed to parse matugen's JSON")?;
+    let colors = json
+        .get("colors")
+        .ok_or_else(|| anyhow!("matugen's JSON has no `colors`"))?;
+
+    // `colors.<mode>.<name>`, or `colors.<name>.<mode>` in newer versions
+    let get = |name: &str| -> Result<Color> {
+        let value = colors
+            .get(mode.as_str())
+            .and_then(|by_mode| by_mode.get(name))
+            .or_else(|| {
+                colors
+                    .get(name)
+                    .and_then(|by_name| by_name.get(mode.as_str()))
+            });
+        value
+            .and_then(|v| {
+                v.as_str()
+                    .or_else(|| v.get("color").and_then(Value::as_str))
+            })
+            .and_then(Color::from_hex)
+            .ok_or_else(|| {
+                anyhow!(
+                    "matugen's JSON has no `{}` color in {} mode",
+                    name,
+                    mode.as_str()
+                )
+            })
+    };
+
+    let (primary, surface, on_surface) = (get("primary")?, get("surface")?, get("on_surface")?);
+    let clusters = [
+        (primary, 0.4),
+        (get("secondary")?, 0.2),
+        (get("tertiary")?, 0.2),
+        (get("error")?, 0.2),
+    ]
+    .map(|(color, weight)| Cluster {
+        color: color.to_oklab(),
+        weight,
+    });
+
+    let mut scheme = derive_scheme(&clusters, mode);
+    scheme.background = surface;
+    scheme.foreground = on_surface;
+    scheme.cursor = primary;
+    scheme.accent = primary;
+    scheme.colors[0] = surface;
+    scheme.colors[7] = get("on_surface_variant")?;
+    scheme.colors[8] = get("outline")?;
+    scheme.colors[15] = on_surface;
+    Ok(scheme)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const WALLPAPER: &str = "/walls/forest.png";
+
+    fn hex(color: Color) -> String {
+        color.hex()
+    }
+
+    fn record_run(cache: &TempDir, generator: Generator, is_light: bool, stdout: Option<&str>) {
+        let run = GeneratorRun::new(
+            Path::new(WALLPAPER),
+            is_light,
+            SystemTime::now() - Duration::from_secs(1),
+            stdout.map(str::to_string),
+        );
+        write_run(&cache.path().join("wallpaper"), generator, &run).unwrap();
+    }
+
+    fn scheme(cache: &TempDir, is_light: bool, generator: Generator) -> Result<Scheme> {
+        scheme_in(cache.path(), Path::new(WALLPAPER), is_light, generator).map(|(s, _)| s)
+    }
+
+    fn sequences() -> String {
+        let mut content = String::new();
+        for index in 0..16 {
+            content.push_str(&format!("\x1b]4;{};#0{:x}0000\x1b\\", index, index));
+        }
+        content.push_str("\x1b]10;#e0e0e0\x1b\\\x1b]11;[100]#101010\x1b\\\x1b]12;#ff00ff\x07");
+        content
+    }
+
+    const MATUGEN_V2: &str = r##"{
+        "image": "/walls/forest.png",
+        "colors": {
+            "dark": {"primary": "#a8c8ff", "secondary": "#bdc7dc", "tertiary": "#dbbce1",
+                     "error": "#ffb4ab", "surface": "#111318", "on_surface": "#e2e2e9",
+                     "on_surface_variant": "#c4c6d0", "outline": "#8e9099"},
+            "light": {"primary": "#3f5f90", "secondary": "#555f71", "tertiary": "#6e5676",
+                      "error": "#ba1a1a", "surface": "#f9f9ff", "on_surface": "#191c20",
+                      "on_surface_variant": "#44474e", "outline": "#74777f"}
+        }
+    }"##;
+
+    #[test]
+    fn test_matugen_json() {
+        let cache = TempDir::new().unwrap();
+        record_run(&cache, Generator::Matugen, false, Some(MATUGEN_V2));
+
+        let dark = scheme(&cache, false, Generator::Matugen).unwrap();
+        assert_eq!(hex(dark.background), "#111318");
+        assert_eq!(hex(dark.foreground), "#e2e2e9");
+        assert_eq!(hex(dark.accent), "#a8c8ff");
+        assert_eq!(hex(dark.colors[8]), "#8e9099");
+        // Red takes the hue of the error color
+        let red = dark.colors[1].to_oklab().hue();
+        let error = Color::from_hex("#ffb4ab").unwrap().to_oklab().hue();
+        assert!((red - error).abs() < 2.0, "{} {}", red, error);
+
+        // The run was dark, so light colors are not there yet
+        let error = format!(
+            "{:#}",
+            scheme(&cache, true, Generator::Matugen).unwrap_err()
+        );
+        assert!(error.contains("in dark mode, not for"), "{}", error);
+    }
+
+    #[test]
+    fn test_matugen_json_by_color() {
+        let cache = TempDir::new().unwrap();
+        let stdout = r##"{"colors": {
+            "primary": {"dark": "#a8c8ff", "light": "#3f5f90"},
+            "secondary": {"dark": "#bdc7dc", "light": "#555f71"},
+            "tertiary": {"dark": "#dbbce1", "light": "#6e5676"},
+            "error": {"dark": "#ffb4ab", "light": "#ba1a1a"},
+            "surface": {"dark": {"color": "#111318"}, "light": {"color": "#f9f9ff"}},
+            "on_surface": {"dark": "#e2e2e9", "light": "#191c20"},
+            "on_surface_variant": {"dark": "#c4c6d0", "light": "#44474e"},
+            "outline": {"dark": "#8e9099", "light": "#74777f"}}}"##;
+        record_run(&cache, Generator::Matugen, true, Some(stdout));
+
+        let light = scheme(&cache, true, Generator::Matugen).unwrap();
+        assert_eq!(hex(light.background), "#f9f9ff");
+        assert_eq!(hex(light.cursor), "#3f5f90");
+    }
+
+    #[test]
+    fn test_matugen_without_json() {
+        let cache = TempDir::new().unwrap();
+        record_run(
+            &cache,
+            Generator::Matugen,
+            false,
+            Some("Setting wallpaper..."),
+        );
+
+        let error = format!(
+            "{:#}",
+            scheme(&cache, false, Generator::Matugen).unwrap_err()
+        );
+        assert!(error.contains("printed no JSON"), "{}", error);
+
+        let incomplete = r##"{"colors": {"dark": {"primary": "#a8c8ff"}}}"##;
+        record_run(&cache, Generator::Matugen, false, Some(incomplete));
+        let error = format!(
+            "{:#}",
+            scheme(&cache, false, Generator::Matugen).unwrap_err()
+        );
+        assert!(
+            error.contains("no `surface` color in dark mode"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_wallust_sequences() {
+        let cache = TempDir::new().unwrap();
+        fs::create_dir_all(cache.path().join("wallust")).unwrap();
+        fs::write(cache.path().join("wallust/sequences"), sequences()).unwrap();
+        record_run(&cache, Generator::Wallust, false, None);
+
+        let scheme = scheme(&cache, false, Generator::Wallust).unwrap();
+        assert_eq!(hex(scheme.background), "#101010");
+        assert_eq!(hex(scheme.foreground), "#e0e0e0");
+        assert_eq!(hex(scheme.cursor), "#ff00ff");
+        assert_eq!(hex(scheme.colors[15]), "#0f0000");
+        assert_eq!(hex(scheme.accent), "#060000");
+    }
+
+    #[test]
+    fn test_wallust_incomplete_sequences() {
+        assert!(sequences_scheme("\x1b]4;0;#000000\x1b\\").is_err());
+        assert!(format!("{:#}", sequences_scheme("").unwrap_err()).contains("color0 is missing"));
+    }
+
+    #[test]
+    fn test_generator_did_not_run() {
+        let cache = TempDir::new().unwrap();
+        let error = format!(
+            "{:#}",
+            scheme(&cache, false, Generator::Wallust).unwrap_err()
+        );
+        assert!(error.contains("has not generated any colors"), "{}", error);
+
+        // Colors of another wallpaper
+        let run = GeneratorRun::new(Path::new("/walls/city.png"), false, SystemTime::now(), None);
+        write_run(&cache.path().join("wallpaper"), Generator::Wallust, &run).unwrap();
+        let error = format!(
+            "{:#}",
+            scheme(&cache, false, Generator::Wallust).unwrap_err()
+        );
+        assert!(error.contains("/walls/city.png"), "{}", error);
+    }
+
+    #[test]
+    fn test_stale_output() {
+        let cache = TempDir::new().unwrap();
+        let sequences_path = cache.path().join("wallust/sequences");
+        fs::create_dir_all(sequences_path.parent().unwrap()).unwrap();
+        fs::write(&sequences_path, sequences()).unwrap();
+
+        // wallust ran later without touching the file
+        let run = GeneratorRun::new(
+            Path::new(WALLPAPER),
+            false,
+            SystemTime::now() + Duration::from_secs(60),
+            None,
+        );
+        write_run(&cache.path().join("wallpaper"), Generator::Wallust, &run).unwrap();
+
+        let error = format!(
+            "{:#}",
+            scheme(&cache, false, Generator::Wallust).unwrap_err()
+        );
+        assert!(error.contains("was not updated"), "{}", error);
+
+        // Missing output
+        fs::remove_file(&sequences_path).unwrap();
+        record_run(&cache, Generator::Wallust, false, None);
+        assert!(scheme(&cache, false, Generator::Wallust).is_err());
+    }
+
+    #[test]
+    fn test_pywal_colors() {
+        let cache = TempDir::new().unwrap();
+        let mut colors = String::new();
+        for index in 0..16 {
+            colors.push_str(&format!("\"color{}\": \"#0{:x}0000\",", index, index));
+        }
+        let pywal = |wallpaper: &str| {
+            format!(
+                r##"{{"wallpaper": "{}", "alpha": "100",
+                    "special": {{"background": "#000000", "foreground": "#ffffff", "cursor": "#ff00ff"}},
+                    "colors": {{{}}}}}"##,
+                wallpaper,
+                colors.trim_end_matches(',')
+            )
+        };
+        fs::create_dir_all(cache.path().join("wal")).unwrap();
+        fs::write(cache.path().join("wal/colors.json"), pywal(WALLPAPER)).unwrap();
+        record_run(&cache, Generator::Pywal16, false, None);
+
+        let scheme_16 = scheme(&cache, false, Generator::Pywal16).unwrap();
+        assert_eq!(scheme_16.background, Color::new(0, 0, 0));
+        assert_eq!(scheme_16.cursor, Color::new(255, 0, 255));
+        assert_eq!(scheme_16.colors[15], Color::new(0x0f, 0, 0));
+        assert_eq!(scheme_16.accent, Color::new(0x06, 0, 0));
+
+        fs::write(
+            cache.path().join("wal/colors.json"),
+            pywal("/walls/city.png"),
+        )
+        .unwrap();
+        record_run(&cache, Generator::Pywal16, false, None);
+        let error = format!(
+            "{:#}",
+            scheme(&cache, false, Generator::Pywal16).unwrap_err()
+        );
+        assert!(
+            error.contains("holds the colors of /walls/city.png"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_native_palette_or_extraction() {
+        let cache = TempDir::new().unwrap();
+        let clusters = [Cluster {
+            color: Color::from_hex("#1d3b2a").unwrap().to_oklab(),
+            weight: 1.0,
+        }];
+        let palette = Palette::from_clusters(Path::new(WALLPAPER), &clusters, false);
+        fs::create_dir_all(cache.path().join("wallpaper")).unwrap();
+        fs::write(
+            cache.path().join("wallpaper").join(PALETTE_FILENAME),
+            serde_json::to_string(&palette).unwrap(),
+        )
+        .unwrap();
+
+        // The saved palette has both modes
+        assert_eq!(
+            scheme(&cache, true, Generator::Native).unwrap(),
+            palette.light
+        );
+
+        // Another wallpaper is extracted on the spot, which fails for a missing file
+        let error = scheme_in(
+            cache.path(),
+            Path::new("/walls/missing.png"),
+            false,
+            Generator::Native,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to open image"));
+    }
+}
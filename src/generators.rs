@@ -0,0 +1,88 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::pipeline::StepContext;
+use crate::programs::Program;
+
+/// Palette generators used when neither `--generator` nor config chooses any
+pub const DEFAULT_GENERATORS: &[Generator] = &[Generator::Matugen, Generator::Wallust];
+
+/// Tool that derives a color palette from the wallpaper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Generator {
+    Matugen,
+    Wallust,
+    Pywal,
+    Pywal16,
+    Hellwal,
+    /// Built-in extractor, needs no external program
+    Native,
+}
+
+impl Generator {
+    /// Name as used by `--generator`, config.toml and pipeline steps
+    pub fn name(self) -> &'static str {
+        match self {
+            Generator::Matugen => "matugen",
+            Generator::Wallust => "wallust",
+            Generator::Pywal => "pywal",
+            Generator::Pywal16 => "pywal16",
+            Generator::Hellwal => "hellwal",
+            Generator::Native => "native",
+        }
+    }
+
+    /// Command for this generator with `--light` and `--type` mapped onto its own flags.
+    /// Only matugen understands scheme types; other tools ignore `--type`.
+    pub fn program(&self, ctx: &StepContext) -> Program {
+        match self {
+            Generator::Matugen => Program::matugen(ctx.path, ctx.matugen_type, ctx.is_light),
+            Generator::Wallust => Program::wallust(ctx.path, ctx.is_light),
+            Generator::Pywal => Program::pywal(ctx.path, ctx.is_light),
+            Generator::Pywal16 => Program::pywal16(ctx.path, ctx.is_light),
+            Generator::Hellwal => Program::hellwal(ctx.path, ctx.is_light),
+            Generator::Native => Program::native(ctx.path, ctx.is_light),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::Backend;
+    use std::path::Path;
+
+    #[test]
+    fn test_generator_step_names() {
+        let ctx = StepContext::new(
+            Path::new("/test/image.jpg"),
+            "scheme-content",
+            false,
+            Backend::Swww,
+            DEFAULT_GENERATORS,
+        );
+
+        for generator in Generator::value_variants() {
+            let name = generator.to_possible_value().unwrap();
+            assert_eq!(generator.program(&ctx).get_step_name(), name.get_name());
+            assert_eq!(generator.name(), name.get_name());
+            assert_eq!(generator.program(&ctx).generator(), Some(*generator));
+        }
+    }
+
+    #[test]
+    fn test_generators_from_config_names() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            generators: Vec<Generator>,
+        }
+
+        let wrapper: Wrapper =
+            toml::from_str(r#"generators = ["pywal16", "hellwal", "matugen"]"#).unwrap();
+        assert_eq!(
+            wrapper.generators,
+            vec![Generator::Pywal16, Generator::Hellwal, Generator::Matugen]
+        );
+    }
+}
@@ -1,13 +1,31 @@
 use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::VecDeque,
     fs,
+    io::Write,
     path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-const MAX_HISTORY_ENTRIES: usize = 50;
+use crate::config::HistoryConfig;
+use crate::images::ImageInfo;
+use crate::logging::{info, warning};
+use crate::tags::TagStore;
+use crate::utils::{lock_exclusive, with_suffix, write_atomic};
+
+/// Default history capacity
+pub const MAX_HISTORY_ENTRIES: usize = 50;
+/// Layout version of history.json written by this build. Any change to the
+/// stored layout bumps it and adds a step to [`MIGRATIONS`].
+const HISTORY_VERSION: u64 = 3;
 const HISTORY_FILENAME: &str = "history.json";
+/// Previous version of history.json, used when the current one is corrupt
+const BACKUP_SUFFIX: &str = ".bak";
+/// A corrupt history.json, kept for inspection when it gets replaced
+const CORRUPT_SUFFIX: &str = ".corrupt";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WallpaperEntry {
@@ -15,92 +33,493 @@ pub struct WallpaperEntry {
     pub timestamp: u64,
     pub matugen_type: String,
     pub is_light: bool,
+    /// Output the wallpaper was set on. `None` means every output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Starred entries are never evicted when the history is full
+    #[serde(default)]
+    pub starred: bool,
+    /// Format and dimensions detected when the wallpaper was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageInfo>,
 }
 
 impl WallpaperEntry {
     pub fn new(path: PathBuf, matugen_type: String, is_light: bool) -> Self {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-
         Self {
             path,
-            timestamp,
+            timestamp: unix_now(),
             matugen_type,
             is_light,
+            output: None,
+            starred: false,
+            image: None,
         }
     }
+
+    pub fn with_output(mut self, output: Option<String>) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn with_image(mut self, image: ImageInfo) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    /// The image file was deleted or moved since the entry was recorded
+    pub fn is_missing(&self) -> bool {
+        !self.path.is_file()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct WallpaperHistory {
-    entries: VecDeque<WallpaperEntry>,
+/// Which entries history keeps. Starred entries and the most recent entry are
+/// exempt from both limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Maximum number of entries, 0 means unlimited
+    pub max_entries: usize,
+    /// Entries older than this are dropped
+    pub max_age: Option<Duration>,
 }
 
-impl Default for WallpaperHistory {
+impl Default for Retention {
     fn default() -> Self {
         Self {
-            entries: VecDeque::with_capacity(MAX_HISTORY_ENTRIES),
+            max_entries: MAX_HISTORY_ENTRIES,
+            max_age: None,
         }
     }
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WallpaperHistory {
+    /// Index of the entry on screen, moved by `prev`/`next` without reordering
+    #[serde(default)]
+    cursor: usize,
+    entries: VecDeque<WallpaperEntry>,
+    #[serde(skip)]
+    retention: Retention,
+}
+
 impl WallpaperHistory {
-    pub fn load() -> Result<Self> {
-        let history_path = get_history_file_path()?;
+    /// Load history for reading. Entries beyond the retention limits and, with
+    /// `auto_prune`, missing images are dropped in memory only; the file is
+    /// cleaned up by the next change.
+    pub fn load(config: &HistoryConfig) -> Result<Self> {
+        Self::load_at(&get_history_file_path()?, config)
+    }
 
-        if !history_path.exists() {
-            return Ok(Self::default());
+    fn load_at(path: &Path, config: &HistoryConfig) -> Result<Self> {
+        let mut history = Self::read_at(path)?;
+        history.set_retention(config.retention());
+        if config.auto_prune {
+            history.prune_missing();
         }
+        Ok(history)
+    }
 
-        let content = fs::read_to_string(&history_path)
-            .with_context(|| format!("Failed to read history file: {}", history_path.display()))?;
+    /// Read-modify-write history.json under the history lock, so concurrent
+    /// runs (e.g. the slideshow daemon and a keybinding) do not lose entries
+    pub fn update<T>(
+        config: &HistoryConfig,
+        modify: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let path = get_history_file_path()?;
+        let (_, result) = Self::update_at(&path, config, modify)?;
+        Ok(result)
+    }
 
-        let history: WallpaperHistory =
-            serde_json::from_str(&content).with_context(|| "Failed to parse history file")?;
+    fn update_at<T>(
+        path: &Path,
+        config: &HistoryConfig,
+        modify: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<(Self, T)> {
+        let _lock = lock_exclusive(&with_suffix(path, ".lock"))?;
 
-        Ok(history)
+        let mut history = Self::read_at(path)?;
+        history.set_retention(config.retention());
+        if config.auto_prune {
+            let pruned = history.prune_missing();
+            if !pruned.is_empty() {
+                info!(
+                    "Pruned {} history entries whose files no longer exist.",
+                    pruned.len()
+                );
+            }
+        }
+
+        let result = modify(&mut history)?;
+        history.write_at(path)?;
+
+        Ok((history, result))
     }
 
-    pub fn save(&self) -> Result<()> {
-        let history_path = get_history_file_path()?;
+    /// Read history.json, falling back to the backup when it is corrupt
+    fn read_at(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read history file: {}", path.display()))?;
+
+        // A file from a newer build is not corrupt; the backup must not silently replace it
+        if let Some(version) = stored_version(&content).filter(|v| *v > HISTORY_VERSION) {
+            return Err(anyhow!(
+                "History file {} has version {}, but this build only understands up to version {}. Update wallpaper.",
+                path.display(),
+                version,
+                HISTORY_VERSION
+            ));
+        }
+
+        let error = match Self::from_json(&content) {
+            Ok(history) => return Ok(history),
+            Err(error) => error,
+        };
 
-        // Ensure parent directory exists
-        if let Some(parent) = history_path.parent() {
+        let backup_path = with_suffix(path, BACKUP_SUFFIX);
+        let backup = fs::read_to_string(&backup_path)
+            .ok()
+            .and_then(|c| Self::from_json(&c).ok());
+
+        match backup {
+            Some(history) => {
+                warning!(
+                    "history file {} is corrupt ({:#}). Using the backup {} with {} entries; the corrupt file is replaced with the next change.",
+                    path.display(),
+                    error,
+                    backup_path.display(),
+                    history.len()
+                );
+                Ok(history)
+            }
+            None => Err(error.context(format!(
+                "Failed to parse history file {} and no usable backup exists at {}",
+                path.display(),
+                backup_path.display()
+            ))),
+        }
+    }
+
+    /// Atomically replace history.json, keeping the previous version as a backup.
+    /// A corrupt previous version is kept aside instead of overwriting the backup.
+    fn write_at(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).with_context(|| {
                 format!("Failed to create history directory: {}", parent.display())
             })?;
         }
 
-        let content =
-            serde_json::to_string_pretty(self).with_context(|| "Failed to serialize history")?;
+        if let Ok(previous) = fs::read(path) {
+            let previous_ok =
+                std::str::from_utf8(&previous).is_ok_and(|c| Self::from_json(c).is_ok());
+            let suffix = if previous_ok {
+                BACKUP_SUFFIX
+            } else {
+                CORRUPT_SUFFIX
+            };
+            write_atomic(&with_suffix(path, suffix), &previous)?;
+        }
 
-        fs::write(&history_path, content)
-            .with_context(|| format!("Failed to write history file: {}", history_path.display()))?;
+        let content = self.to_json()?;
 
-        Ok(())
+        write_atomic(path, content.as_bytes())
+            .with_context(|| format!("Failed to write history file: {}", path.display()))
+    }
+
+    /// Parse history.json of any known version, upgrading older layouts
+    pub fn from_json(content: &str) -> Result<Self> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(content).with_context(|| "Invalid JSON")?;
+
+        let version = match value.get("version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .filter(|v| (1..=HISTORY_VERSION).contains(v))
+                .ok_or_else(|| anyhow!("Unsupported history version: {}", version))?,
+        };
+
+        for migrate in &MIGRATIONS[version as usize - 1..] {
+            migrate(&mut value)?;
+        }
+
+        serde_json::from_value(value).with_context(|| "Invalid history layout")
+    }
+
+    /// Serialize with the current layout version
+    pub fn to_json(&self) -> Result<String> {
+        #[derive(Serialize)]
+        struct Versioned<'a> {
+            version: u64,
+            #[serde(flatten)]
+            history: &'a WallpaperHistory,
+        }
+
+        serde_json::to_string_pretty(&Versioned {
+            version: HISTORY_VERSION,
+            history: self,
+        })
+        .with_context(|| "Failed to serialize history")
     }
 
+    #[allow(dead_code)]
     pub fn add_entry(&mut self, path: PathBuf, matugen_type: String, is_light: bool) {
-        let entry = WallpaperEntry::new(path, matugen_type, is_light);
+        self.push_entry(WallpaperEntry::new(path, matugen_type, is_light));
+    }
 
-        // Remove duplicate if exists
-        self.entries.retain(|e| e.path != entry.path);
+    pub fn push_entry(&mut self, mut entry: WallpaperEntry) {
+        // Remove duplicate if exists (the same image on another output is not a duplicate).
+        // Setting a starred wallpaper again keeps it starred.
+        self.entries.retain(|e| {
+            let duplicate = e.path == entry.path && e.output == entry.output;
+            entry.starred |= duplicate && e.starred;
+            !duplicate
+        });
 
-        // Add to front
+        // Add to front; a new wallpaper ends any walk through history
         self.entries.push_front(entry);
+        self.cursor = 0;
+
+        self.apply_retention(unix_now());
+    }
+
+    /// Change the limits and drop whatever no longer fits
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.apply_retention(unix_now());
+    }
+
+    /// Drop unstarred entries older than `max_age`, then evict the oldest
+    /// unstarred entries beyond `max_entries`. The most recent entry is always
+    /// kept. Returns the number of removed entries.
+    pub fn apply_retention(&mut self, now: u64) -> usize {
+        let before = self.entries.len();
+
+        if let Some(max_age) = self.retention.max_age {
+            let cutoff = now.saturating_sub(max_age.as_secs());
+            self.retain_entries(|index, e| index == 0 || e.starred || e.timestamp >= cutoff);
+        }
+
+        let max_entries = self.retention.max_entries;
+        while max_entries > 0 && self.entries.len() > max_entries {
+            let oldest = (1..self.entries.len())
+                .rev()
+                .find(|&i| !self.entries[i].starred);
+            match oldest {
+                Some(oldest) => self.retain_entries(|index, _| index != oldest),
+                None => break,
+            };
+        }
+
+        before - self.entries.len()
+    }
+
+    /// Remove and return the entries whose image file no longer exists
+    pub fn prune_missing(&mut self) -> Vec<WallpaperEntry> {
+        self.retain_entries(|_, e| !e.is_missing())
+    }
 
-        // Keep only MAX_HISTORY_ENTRIES
-        if self.entries.len() > MAX_HISTORY_ENTRIES {
-            self.entries.pop_back();
+    /// Keep the entries matching `keep` and return the others. The cursor stays
+    /// on its entry, or moves to the next older one that is kept.
+    fn retain_entries(
+        &mut self,
+        mut keep: impl FnMut(usize, &WallpaperEntry) -> bool,
+    ) -> Vec<WallpaperEntry> {
+        let mut kept = VecDeque::with_capacity(self.entries.len());
+        let mut removed = Vec::new();
+        let mut cursor = None;
+
+        for (index, entry) in self.entries.drain(..).enumerate() {
+            if keep(index, &entry) {
+                if index >= self.cursor && cursor.is_none() {
+                    cursor = Some(kept.len());
+                }
+                kept.push_back(entry);
+            } else {
+                removed.push(entry);
+            }
+        }
+
+        self.cursor = cursor.unwrap_or(kept.len().saturating_sub(1));
+        self.entries = kept;
+        removed
+    }
+
+    /// Index of the entry currently on screen
+    pub fn cursor(&self) -> usize {
+        self.cursor.min(self.entries.len().saturating_sub(1))
+    }
+
+    /// The entry currently on screen: the most recent one unless `prev` moved back
+    pub fn current(&self) -> Option<&WallpaperEntry> {
+        self.entries.get(self.cursor())
+    }
+
+    /// The entry last shown on `outputs` (any output when empty), from the cursor on
+    pub fn on_screen(&self, outputs: &[String]) -> Option<&WallpaperEntry> {
+        self.entries
+            .iter()
+            .skip(self.cursor())
+            .find(|e| match &e.output {
+                Some(output) => outputs.is_empty() || outputs.contains(output),
+                None => true,
+            })
+    }
+
+    /// Index of the entry `step` leads to from the cursor. Entries whose image
+    /// is gone are skipped with a warning.
+    pub fn navigate(&self, step: Navigation) -> Result<usize> {
+        if self.entries.is_empty() {
+            return Err(anyhow!("No wallpaper history found."));
+        }
+
+        let cursor = self.cursor();
+        let candidates: Box<dyn Iterator<Item = usize>> = match step {
+            Navigation::Prev => Box::new(cursor + 1..self.entries.len()),
+            Navigation::Next => Box::new((0..cursor).rev()),
+            Navigation::Latest => {
+                let entry = &self.entries[0];
+                if entry.is_missing() {
+                    return Err(missing_error(entry));
+                }
+                return Ok(0);
+            }
+        };
+
+        for index in candidates {
+            let entry = &self.entries[index];
+            if !entry.is_missing() {
+                return Ok(index);
+            }
+            warning!("skipping missing wallpaper {}", entry.path.display());
+        }
+
+        Err(match step {
+            Navigation::Prev => anyhow!(
+                "Already at the oldest wallpaper in history ({} entries).",
+                self.entries.len()
+            ),
+            _ => anyhow!("Already at the latest wallpaper."),
+        })
+    }
+
+    /// Move the cursor to `entry`, wherever it is now. Returns false if it is
+    /// no longer in history.
+    pub fn set_cursor(&mut self, entry: &WallpaperEntry) -> bool {
+        let position = self.entries.iter().position(|e| {
+            e.path == entry.path && e.output == entry.output && e.timestamp == entry.timestamp
+        });
+        if let Some(position) = position {
+            self.cursor = position;
+        }
+        position.is_some()
+    }
+
+    /// Star or unstar the entry at 1-based `step`
+    pub fn set_starred(&mut self, step: usize, starred: bool) -> Result<&WallpaperEntry> {
+        let len = self.entries.len();
+        let entry = step
+            .checked_sub(1)
+            .and_then(|index| self.entries.get_mut(index))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid history entry: {}. History has {} entries.",
+                    step,
+                    len
+                )
+            })?;
+
+        entry.starred = starred;
+        Ok(entry)
+    }
+
+    /// Entries matching `filter`, paired with their 0-based index
+    pub fn filtered(
+        &self,
+        filter: &HistoryFilter,
+        tags: &TagStore,
+    ) -> Vec<(usize, &WallpaperEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| filter.matches(e, tags))
+            .collect()
+    }
+
+    /// Starred entry whose file name, stem or full path is `name`. Falls back to
+    /// a case-insensitive substring of the file name when nothing matches exactly.
+    pub fn find_favorite(&self, name: &str) -> Result<&WallpaperEntry> {
+        let favorites: Vec<&WallpaperEntry> = self.entries.iter().filter(|e| e.starred).collect();
+        if favorites.is_empty() {
+            return Err(anyhow!(
+                "No starred wallpapers. Star one with `wallpaper history star N`."
+            ));
+        }
+
+        let file_name = |e: &WallpaperEntry| {
+            e.path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        let exact: Vec<&WallpaperEntry> = favorites
+            .iter()
+            .copied()
+            .filter(|e| {
+                file_name(e) == name
+                    || e.path.file_stem().is_some_and(|s| s == name)
+                    || e.path.as_os_str() == name
+            })
+            .collect();
+
+        let matches = if exact.is_empty() {
+            let needle = name.to_lowercase();
+            favorites
+                .into_iter()
+                .filter(|e| file_name(e).to_lowercase().contains(&needle))
+                .collect()
+        } else {
+            exact
+        };
+
+        // The same image starred on several outputs is still one favorite
+        let mut paths: Vec<&PathBuf> = matches.iter().map(|e| &e.path).collect();
+        paths.sort();
+        paths.dedup();
+
+        match paths.len() {
+            0 => Err(anyhow!("No starred wallpaper matches \"{}\".", name)),
+            1 => Ok(matches[0]),
+            _ => Err(anyhow!(
+                "\"{}\" matches several starred wallpapers: {}",
+                name,
+                paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
         }
     }
 
+    #[allow(dead_code)]
     pub fn get_entries(&self) -> &VecDeque<WallpaperEntry> {
         &self.entries
     }
 
+    /// Paths of the `count` most recent entries
+    pub fn recent_paths(&self, count: usize) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .take(count)
+            .map(|e| e.path.clone())
+            .collect()
+    }
+
     pub fn get_entry(&self, index: usize) -> Option<&WallpaperEntry> {
         self.entries.get(index)
     }
@@ -114,12 +533,53 @@ impl WallpaperHistory {
     }
 }
 
+/// Step `i` upgrades a version `i + 1` document to version `i + 2`
+type Migration = fn(&mut serde_json::Value) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[migrate_v1_to_v2, migrate_v2_to_v3];
+
+/// Version 1 is the original unversioned `{"entries": [...]}` layout. Entry fields
+/// added before versioning (output, starred, image) are optional, so only the
+/// version number is added.
+fn migrate_v1_to_v2(value: &mut serde_json::Value) -> Result<()> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("History must be a JSON object"))?;
+    object.insert("version".to_string(), 2.into());
+    Ok(())
+}
+
+/// Version 3 adds the `prev`/`next` cursor, starting at the most recent entry
+fn migrate_v2_to_v3(value: &mut serde_json::Value) -> Result<()> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("History must be a JSON object"))?;
+    object.insert("cursor".to_string(), 0.into());
+    object.insert("version".to_string(), 3.into());
+    Ok(())
+}
+
+/// `version` of a history document, `None` when missing or not JSON
+fn stored_version(content: &str) -> Option<u64> {
+    serde_json::from_str::<serde_json::Value>(content)
+        .ok()?
+        .get("version")?
+        .as_u64()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 fn get_history_file_path() -> Result<PathBuf> {
     let data_dir = get_data_directory()?;
     Ok(data_dir.join(HISTORY_FILENAME))
 }
 
-fn get_data_directory() -> Result<PathBuf> {
+pub fn get_data_directory() -> Result<PathBuf> {
     // Try to use XDG_DATA_HOME first, fallback to ~/.local/share
     if let Ok(xdg_data_home) = std::env::var("XDG_DATA_HOME") {
         Ok(PathBuf::from(xdg_data_home).join("wallpaper"))
@@ -132,43 +592,347 @@ fn get_data_directory() -> Result<PathBuf> {
     }
 }
 
-pub fn add_to_history(path: &Path, matugen_type: &str, is_light: bool) -> Result<()> {
-    let mut history = WallpaperHistory::load()?;
-    history.add_entry(path.to_path_buf(), matugen_type.to_string(), is_light);
-    history.save()?;
-    Ok(())
+/// Direction of `wallpaper prev`, `next` and `back-to-latest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Navigation {
+    /// One entry older than the cursor
+    Prev,
+    /// One entry newer than the cursor
+    Next,
+    /// The most recent entry
+    Latest,
+}
+
+pub fn add_to_history(entry: WallpaperEntry, config: &HistoryConfig) -> Result<()> {
+    WallpaperHistory::update(config, |history| {
+        history.push_entry(entry);
+        Ok(())
+    })
 }
 
-pub fn display_history() -> Result<()> {
-    let history = WallpaperHistory::load()?;
+/// Which entries `wallpaper history` lists. Every set criterion must match.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub favorites: bool,
+    /// Normalized tags the image must all have
+    pub tags: Vec<String>,
+    /// Case-insensitive substring of the path
+    pub grep: Option<String>,
+    pub matugen_type: Option<String>,
+    pub is_light: Option<bool>,
+    /// Inclusive lower bound, seconds since the epoch
+    pub since: Option<u64>,
+    /// Exclusive upper bound, seconds since the epoch
+    pub until: Option<u64>,
+}
 
-    if history.is_empty() {
-        println!("No wallpaper history found.");
+impl HistoryFilter {
+    fn matches(&self, entry: &WallpaperEntry, tags: &TagStore) -> bool {
+        let grep_ok = self.grep.as_ref().is_none_or(|needle| {
+            entry
+                .path
+                .to_string_lossy()
+                .to_lowercase()
+                .contains(&needle.to_lowercase())
+        });
+
+        (!self.favorites || entry.starred)
+            && tags.has_all(&entry.path, &self.tags)
+            && grep_ok
+            && self
+                .matugen_type
+                .as_ref()
+                .is_none_or(|t| *t == entry.matugen_type)
+            && self.is_light.is_none_or(|l| l == entry.is_light)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+}
+
+/// Point in time given to `--since`/`--until`: a duration ago (`2d`, `1h 30m`),
+/// a date (`2026-10-01`) or a date and time (`2026-10-01 18:30`). Dates are UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSpec {
+    Ago(Duration),
+    At(SystemTime),
+    /// A whole day, starting at the given midnight
+    Day(SystemTime),
+}
+
+impl FromStr for TimeSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Ok(duration) = humantime::parse_duration(s) {
+            return Ok(TimeSpec::Ago(duration));
+        }
+        if let Ok(day) = humantime::parse_rfc3339_weak(&format!("{} 00:00:00", s)) {
+            return Ok(TimeSpec::Day(day));
+        }
+        // Allow leaving out the seconds
+        if let Ok(time) = humantime::parse_rfc3339_weak(&format!("{}:00", s)) {
+            return Ok(TimeSpec::At(time));
+        }
+        humantime::parse_rfc3339_weak(s)
+            .map(TimeSpec::At)
+            .map_err(|_| {
+                format!(
+                    "invalid time `{}`: expected a duration ago (2d, 1h 30m) or a date (2026-10-01, 2026-10-01 18:30)",
+                    s
+                )
+            })
+    }
+}
+
+impl TimeSpec {
+    /// Seconds since the epoch. A date used as an upper bound includes that whole day.
+    pub fn resolve(self, now: SystemTime, upper_bound: bool) -> u64 {
+        let time = match self {
+            TimeSpec::Ago(duration) => now.checked_sub(duration).unwrap_or(UNIX_EPOCH),
+            TimeSpec::At(time) => time,
+            TimeSpec::Day(day) if upper_bound => day + Duration::from_secs(24 * 60 * 60),
+            TimeSpec::Day(day) => day,
+        };
+
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// Output format of `wallpaper history`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum HistoryFormat {
+    /// Human-readable table
+    #[default]
+    Table,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+/// Flat view of an entry for machine-readable output. `index` is the
+/// 1-based step accepted by `wallpaper restore`. `tags` is a list in JSON
+/// and a `;`-separated string in CSV, which has no nested values.
+#[derive(Debug, Serialize)]
+struct EntryRecord<'a, T> {
+    index: usize,
+    path: String,
+    timestamp: u64,
+    time: String,
+    matugen_type: &'a str,
+    is_light: bool,
+    output: Option<&'a str>,
+    starred: bool,
+    missing: bool,
+    format: Option<&'static str>,
+    width: Option<u32>,
+    height: Option<u32>,
+    tags: T,
+}
+
+impl<'a, T> EntryRecord<'a, T> {
+    fn new(index: usize, entry: &'a WallpaperEntry, tags: T) -> Self {
+        Self {
+            index: index + 1,
+            path: entry.path.to_string_lossy().into_owned(),
+            timestamp: entry.timestamp,
+            time: format_timestamp(entry.timestamp),
+            matugen_type: &entry.matugen_type,
+            is_light: entry.is_light,
+            output: entry.output.as_deref(),
+            starred: entry.starred,
+            missing: entry.is_missing(),
+            format: entry.image.map(|i| i.format.name()),
+            width: entry.image.map(|i| i.width),
+            height: entry.image.map(|i| i.height),
+            tags,
+        }
+    }
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp);
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+pub fn display_history(
+    format: HistoryFormat,
+    filter: &HistoryFilter,
+    config: &HistoryConfig,
+) -> Result<()> {
+    let history = WallpaperHistory::load(config)?;
+    let tags = TagStore::load()?;
+    let entries = history.filtered(filter, &tags);
+    let mut stdout = std::io::stdout().lock();
+    write_history(&entries, &tags, format, &mut stdout)
+}
+
+pub fn write_history(
+    entries: &[(usize, &WallpaperEntry)],
+    tags: &TagStore,
+    format: HistoryFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let entry_tags = |entry: &WallpaperEntry| -> Vec<&str> {
+        tags.get(&entry.path)
+            .map(|t| t.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    };
+    let records = entries
+        .iter()
+        .map(|&(index, entry)| EntryRecord::new(index, entry, entry_tags(entry)));
+
+    match format {
+        HistoryFormat::Table => write_table(entries, tags, out)?,
+        HistoryFormat::Json => {
+            let records: Vec<_> = records.collect();
+            serde_json::to_writer_pretty(&mut *out, &records)
+                .with_context(|| "Failed to serialize history")?;
+            writeln!(out)?;
+        }
+        HistoryFormat::Ndjson => {
+            for record in records {
+                serde_json::to_writer(&mut *out, &record)
+                    .with_context(|| "Failed to serialize history")?;
+                writeln!(out)?;
+            }
+        }
+        HistoryFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut *out);
+            // Written explicitly so an empty history still has a header row
+            writer.write_record([
+                "index",
+                "path",
+                "timestamp",
+                "time",
+                "matugen_type",
+                "is_light",
+                "output",
+                "starred",
+                "missing",
+                "format",
+                "width",
+                "height",
+                "tags",
+            ])?;
+            for &(index, entry) in entries {
+                let tags = entry_tags(entry).join(";");
+                writer.serialize(EntryRecord::new(index, entry, tags))?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_table(
+    entries: &[(usize, &WallpaperEntry)],
+    tags: &TagStore,
+    out: &mut impl Write,
+) -> Result<()> {
+    if entries.is_empty() {
+        writeln!(out, "No wallpaper history found.")?;
         return Ok(());
     }
 
-    println!("Wallpaper History ({} entries):", history.len());
-    println!("{:-<80}", "");
+    writeln!(out, "Wallpaper History ({} entries):", entries.len())?;
+    writeln!(out, "{:-<80}", "")?;
 
-    for (index, entry) in history.get_entries().iter().enumerate() {
-        let timestamp = std::time::UNIX_EPOCH + std::time::Duration::from_secs(entry.timestamp);
-        let datetime = humantime::format_rfc3339_seconds(timestamp);
+    for &(index, entry) in entries {
+        let image = entry
+            .image
+            .map(|i| i.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
 
-        println!(
-            "{:2}: {} | {} | Type: {} | Light: {}",
+        write!(
+            out,
+            "{:2}{} {}{} | {} | Type: {} | Light: {} | Output: {} | Image: {}",
             index + 1,
+            if entry.starred { "*" } else { ":" },
             entry.path.display(),
-            datetime,
+            if entry.is_missing() { " [missing]" } else { "" },
+            format_timestamp(entry.timestamp),
             entry.matugen_type,
-            entry.is_light
+            entry.is_light,
+            entry.output.as_deref().unwrap_or("all"),
+            image
+        )?;
+        if let Some(entry_tags) = tags.get(&entry.path) {
+            let entry_tags: Vec<&str> = entry_tags.iter().map(String::as_str).collect();
+            write!(out, " | Tags: {}", entry_tags.join(", "))?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Remove entries whose image file no longer exists, printing each one
+pub fn gc_history(config: &HistoryConfig, dry_run: bool) -> Result<()> {
+    // Without auto-prune, so the entries are reported here
+    let config = HistoryConfig {
+        auto_prune: false,
+        ..config.clone()
+    };
+    let missing = if dry_run {
+        WallpaperHistory::load(&config)?.prune_missing()
+    } else {
+        WallpaperHistory::update(&config, |history| Ok(history.prune_missing()))?
+    };
+
+    if missing.is_empty() {
+        info!("No missing wallpapers in history.");
+        return Ok(());
+    }
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    info!("{} {} missing entries:", verb, missing.len());
+    for entry in &missing {
+        info!(
+            "  {}{}",
+            entry.path.display(),
+            if entry.starred { " (starred)" } else { "" }
         );
     }
+    Ok(())
+}
 
+/// Star or unstar a history entry and save the history
+pub fn star_entry(step: usize, starred: bool, config: &HistoryConfig) -> Result<()> {
+    let path = WallpaperHistory::update(config, |history| {
+        Ok(history.set_starred(step, starred)?.path.clone())
+    })?;
+    println!(
+        "{} {}",
+        if starred { "Starred" } else { "Unstarred" },
+        path.display()
+    );
     Ok(())
 }
 
-pub fn restore_from_history(step: i32) -> Result<WallpaperEntry> {
-    let history = WallpaperHistory::load()?;
+/// Most recent entry of the starred wallpaper matching `name`
+pub fn restore_favorite(name: &str, config: &HistoryConfig) -> Result<WallpaperEntry> {
+    let history = WallpaperHistory::load(config)?;
+    let entry = history.find_favorite(name)?;
+
+    if entry.is_missing() {
+        return Err(missing_error(entry));
+    }
+
+    Ok(entry.clone())
+}
+
+pub fn restore_from_history(step: i32, config: &HistoryConfig) -> Result<WallpaperEntry> {
+    let history = WallpaperHistory::load(config)?;
 
     if history.is_empty() {
         return Err(anyhow!("No wallpaper history found."));
@@ -189,19 +953,46 @@ pub fn restore_from_history(step: i32) -> Result<WallpaperEntry> {
     })?;
 
     // Check if the file still exists
-    if !entry.path.exists() {
-        return Err(anyhow!(
-            "Wallpaper file no longer exists: {}",
-            entry.path.display()
-        ));
+    if entry.is_missing() {
+        return Err(missing_error(entry));
     }
 
     Ok(entry.clone())
 }
 
+/// Entry that `step` leads to from the cursor, with its index
+pub fn navigation_target(
+    step: Navigation,
+    config: &HistoryConfig,
+) -> Result<(usize, WallpaperEntry)> {
+    let history = WallpaperHistory::load(config)?;
+    let index = history.navigate(step)?;
+    Ok((index, history.entries[index].clone()))
+}
+
+/// Save `entry` as the one on screen once it has been applied
+pub fn move_cursor(entry: &WallpaperEntry, config: &HistoryConfig) -> Result<()> {
+    let found = WallpaperHistory::update(config, |history| Ok(history.set_cursor(entry)))?;
+    if !found {
+        warning!(
+            "{} left history while it was being applied; the history position is unchanged",
+            entry.path.display()
+        );
+    }
+    Ok(())
+}
+
+fn missing_error(entry: &WallpaperEntry) -> anyhow::Error {
+    anyhow!(
+        "Wallpaper file no longer exists: {}. Run `wallpaper history gc` to remove missing entries.",
+        entry.path.display()
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_wallpaper_entry_creation() {
@@ -238,6 +1029,795 @@ mod tests {
         assert!(history.get_entry(0).unwrap().is_light);
     }
 
+    #[test]
+    fn test_history_same_image_on_different_outputs() {
+        let mut history = WallpaperHistory::default();
+        let path = PathBuf::from("/test/image.jpg");
+        let entry = |output: &str| {
+            WallpaperEntry::new(path.clone(), "scheme-tonal-spot".to_string(), false)
+                .with_output(Some(output.to_string()))
+        };
+
+        history.push_entry(entry("DP-1"));
+        history.push_entry(entry("HDMI-A-1"));
+        history.push_entry(entry("DP-1"));
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history.get_entry(0).unwrap().output.as_deref(),
+            Some("DP-1")
+        );
+        assert_eq!(
+            history.get_entry(1).unwrap().output.as_deref(),
+            Some("HDMI-A-1")
+        );
+    }
+
+    #[test]
+    fn test_deserialize_entry_without_output() {
+        let json = r#"{"entries":[{"path":"/test/image.jpg","timestamp":1,"matugen_type":"scheme-content","is_light":false}]}"#;
+        let history: WallpaperHistory = serde_json::from_str(json).unwrap();
+
+        assert_eq!(history.len(), 1);
+        assert!(history.get_entry(0).unwrap().output.is_none());
+        assert!(history.get_entry(0).unwrap().image.is_none());
+        assert!(!history.get_entry(0).unwrap().starred);
+    }
+
+    fn history_with(count: usize) -> WallpaperHistory {
+        let mut history = WallpaperHistory::default();
+        for i in 0..count {
+            let path = PathBuf::from(format!("/test/image{}.jpg", i));
+            history.add_entry(path, "scheme-tonal-spot".to_string(), false);
+        }
+        history
+    }
+
+    #[test]
+    fn test_starred_entries_are_never_evicted() {
+        let mut history = history_with(3);
+        // image0 is the oldest entry
+        history.set_starred(3, true).unwrap();
+
+        for i in 3..MAX_HISTORY_ENTRIES + 10 {
+            let path = PathBuf::from(format!("/test/image{}.jpg", i));
+            history.add_entry(path, "scheme-tonal-spot".to_string(), false);
+        }
+
+        assert_eq!(history.len(), MAX_HISTORY_ENTRIES);
+        let oldest = history.get_entry(MAX_HISTORY_ENTRIES - 1).unwrap();
+        assert_eq!(oldest.path, PathBuf::from("/test/image0.jpg"));
+        assert!(oldest.starred);
+    }
+
+    #[test]
+    fn test_history_grows_when_everything_is_starred() {
+        let mut history = history_with(MAX_HISTORY_ENTRIES);
+        for step in 1..=MAX_HISTORY_ENTRIES {
+            history.set_starred(step, true).unwrap();
+        }
+
+        history.add_entry(
+            PathBuf::from("/test/new.jpg"),
+            "scheme-tonal-spot".to_string(),
+            false,
+        );
+
+        assert_eq!(history.len(), MAX_HISTORY_ENTRIES + 1);
+    }
+
+    #[test]
+    fn test_setting_starred_wallpaper_again_keeps_star() {
+        let mut history = history_with(2);
+        history.set_starred(2, true).unwrap();
+
+        history.add_entry(
+            PathBuf::from("/test/image0.jpg"),
+            "scheme-content".to_string(),
+            false,
+        );
+
+        let entry = history.get_entry(0).unwrap();
+        assert_eq!(entry.path, PathBuf::from("/test/image0.jpg"));
+        assert!(entry.starred);
+    }
+
+    #[test]
+    fn test_set_starred_invalid_step() {
+        let mut history = history_with(2);
+        assert!(history.set_starred(0, true).is_err());
+        assert!(history.set_starred(3, true).is_err());
+        assert!(history.set_starred(2, false).is_ok());
+    }
+
+    #[test]
+    fn test_favorites_filter() {
+        let mut history = history_with(3);
+        history.set_starred(2, true).unwrap();
+
+        let filter = HistoryFilter {
+            favorites: true,
+            ..Default::default()
+        };
+        let favorites = history.filtered(&filter, &TagStore::default());
+        assert_eq!(favorites.len(), 1);
+        assert_eq!(favorites[0].0, 1);
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let history = history_with(3);
+        let mut tags = TagStore::default();
+        tags.add(Path::new("/test/image0.jpg"), &["nature".to_string()])
+            .unwrap();
+        tags.add(
+            Path::new("/test/image2.jpg"),
+            &["nature".to_string(), "work".to_string()],
+        )
+        .unwrap();
+
+        let filter = |list: &[&str]| HistoryFilter {
+            tags: list.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+
+        let nature = history.filtered(&filter(&["nature"]), &tags);
+        assert_eq!(
+            nature.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+        assert_eq!(
+            history.filtered(&filter(&["nature", "work"]), &tags).len(),
+            1
+        );
+        assert!(history.filtered(&filter(&["dark"]), &tags).is_empty());
+    }
+
+    #[test]
+    fn test_field_filters() {
+        let mut history = WallpaperHistory::default();
+        let entries = [
+            ("/walls/Forest.jpg", "scheme-fidelity", true, 1_000),
+            ("/walls/forest-night.jpg", "scheme-fidelity", false, 2_000),
+            ("/walls/city.jpg", "scheme-fidelity", true, 3_000),
+            ("/walls/lake.jpg", "scheme-content", true, 4_000),
+        ];
+        for (path, matugen_type, is_light, timestamp) in entries {
+            let mut entry =
+                WallpaperEntry::new(PathBuf::from(path), matugen_type.to_string(), is_light);
+            entry.timestamp = timestamp;
+            history.push_entry(entry);
+        }
+
+        let paths = |filter: HistoryFilter| -> Vec<String> {
+            history
+                .filtered(&filter, &TagStore::default())
+                .iter()
+                .map(|(_, e)| e.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            paths(HistoryFilter {
+                grep: Some("FOREST".to_string()),
+                matugen_type: Some("scheme-fidelity".to_string()),
+                is_light: Some(true),
+                ..Default::default()
+            }),
+            vec!["Forest.jpg"]
+        );
+        assert_eq!(
+            paths(HistoryFilter {
+                since: Some(2_000),
+                until: Some(4_000),
+                ..Default::default()
+            }),
+            vec!["city.jpg", "forest-night.jpg"]
+        );
+        assert_eq!(paths(HistoryFilter::default()).len(), 4);
+    }
+
+    #[test]
+    fn test_time_spec_parse() {
+        let day = UNIX_EPOCH + Duration::from_secs(1_790_812_800); // 2026-10-01
+        assert_eq!(
+            "2d".parse(),
+            Ok(TimeSpec::Ago(Duration::from_secs(172_800)))
+        );
+        assert_eq!(
+            "1h 30m".parse(),
+            Ok(TimeSpec::Ago(Duration::from_secs(5_400)))
+        );
+        assert_eq!("2026-10-01".parse(), Ok(TimeSpec::Day(day)));
+        assert_eq!(
+            "2026-10-01 18:30".parse(),
+            Ok(TimeSpec::At(day + Duration::from_secs(66_600)))
+        );
+        assert_eq!(
+            "2026-10-01T18:30:15Z".parse(),
+            Ok(TimeSpec::At(day + Duration::from_secs(66_615)))
+        );
+        assert!("last week".parse::<TimeSpec>().is_err());
+    }
+
+    #[test]
+    fn test_time_spec_resolve() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let day = UNIX_EPOCH + Duration::from_secs(86_400);
+
+        assert_eq!(
+            TimeSpec::Ago(Duration::from_secs(100)).resolve(now, false),
+            999_900
+        );
+        assert_eq!(TimeSpec::Ago(Duration::MAX).resolve(now, false), 0);
+        assert_eq!(TimeSpec::Day(day).resolve(now, false), 86_400);
+        // `--until 1970-01-02` includes the whole day
+        assert_eq!(TimeSpec::Day(day).resolve(now, true), 172_800);
+        assert_eq!(TimeSpec::At(day).resolve(now, true), 86_400);
+    }
+
+    #[test]
+    fn test_prune_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = dir.path().join("present.jpg");
+        fs::write(&present, b"").unwrap();
+
+        let mut history = WallpaperHistory::default();
+        for path in [dir.path().join("gone.jpg"), present.clone()] {
+            history.add_entry(path, "scheme-tonal-spot".to_string(), false);
+        }
+        assert!(history.get_entry(1).unwrap().is_missing());
+
+        let pruned = history.prune_missing();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].path, dir.path().join("gone.jpg"));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.get_entry(0).unwrap().path, present);
+        assert!(history.prune_missing().is_empty());
+    }
+
+    /// History of `count` existing images in `dir`, image0 being the oldest
+    fn history_with_files(dir: &Path, count: usize) -> WallpaperHistory {
+        let mut history = WallpaperHistory::default();
+        for i in 0..count {
+            let path = dir.join(format!("image{}.jpg", i));
+            fs::write(&path, b"").unwrap();
+            history.add_entry(path, "scheme-tonal-spot".to_string(), false);
+        }
+        history
+    }
+
+    fn move_to(history: &mut WallpaperHistory, step: Navigation) -> usize {
+        let index = history.navigate(step).unwrap();
+        let entry = history.get_entry(index).unwrap().clone();
+        assert!(history.set_cursor(&entry));
+        index
+    }
+
+    #[test]
+    fn test_navigate_back_and_forth() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = history_with_files(dir.path(), 3);
+        let order: Vec<PathBuf> = history.recent_paths(3);
+
+        assert_eq!(move_to(&mut history, Navigation::Prev), 1);
+        assert_eq!(move_to(&mut history, Navigation::Prev), 2);
+        assert!(history.navigate(Navigation::Prev).is_err());
+        assert_eq!(history.current().unwrap().path, order[2]);
+
+        assert_eq!(move_to(&mut history, Navigation::Next), 1);
+        assert_eq!(move_to(&mut history, Navigation::Latest), 0);
+        assert!(history.navigate(Navigation::Next).is_err());
+
+        // Walking never reorders history
+        assert_eq!(history.recent_paths(3), order);
+    }
+
+    #[test]
+    fn test_navigate_skips_missing_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = history_with_files(dir.path(), 3);
+        fs::remove_file(dir.path().join("image1.jpg")).unwrap();
+
+        assert_eq!(move_to(&mut history, Navigation::Prev), 2);
+        assert_eq!(move_to(&mut history, Navigation::Next), 0);
+        assert!(
+            WallpaperHistory::default()
+                .navigate(Navigation::Latest)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_on_screen_per_output() {
+        let mut history = WallpaperHistory::default();
+        let entry = |path: &str, output: Option<&str>| {
+            WallpaperEntry::new(PathBuf::from(path), "scheme-tonal-spot".to_string(), false)
+                .with_output(output.map(str::to_string))
+        };
+        history.push_entry(entry("/all.jpg", None));
+        history.push_entry(entry("/left.jpg", Some("DP-1")));
+        history.push_entry(entry("/right.jpg", Some("HDMI-A-1")));
+
+        let on = |outputs: &[&str]| {
+            let outputs: Vec<String> = outputs.iter().map(|o| o.to_string()).collect();
+            history.on_screen(&outputs).unwrap().path.clone()
+        };
+        assert_eq!(on(&[]), PathBuf::from("/right.jpg"));
+        assert_eq!(on(&["DP-1"]), PathBuf::from("/left.jpg"));
+        assert_eq!(on(&["eDP-1"]), PathBuf::from("/all.jpg"));
+    }
+
+    #[test]
+    fn test_new_entry_resets_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = history_with_files(dir.path(), 3);
+        move_to(&mut history, Navigation::Prev);
+
+        history.add_entry(
+            dir.path().join("new.jpg"),
+            "scheme-tonal-spot".to_string(),
+            false,
+        );
+        assert_eq!(history.cursor(), 0);
+    }
+
+    #[test]
+    fn test_cursor_follows_its_entry_when_entries_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = history_with_files(dir.path(), 4);
+        move_to(&mut history, Navigation::Prev);
+        move_to(&mut history, Navigation::Prev);
+        let current = history.current().unwrap().path.clone();
+
+        // A newer entry disappears: the cursor keeps pointing at the same image
+        fs::remove_file(dir.path().join("image2.jpg")).unwrap();
+        history.prune_missing();
+        assert_eq!(history.cursor(), 1);
+        assert_eq!(history.current().unwrap().path, current);
+
+        // The current entry itself disappears: the next older one takes over
+        fs::remove_file(&current).unwrap();
+        history.prune_missing();
+        assert_eq!(
+            history.current().unwrap().path,
+            dir.path().join("image0.jpg")
+        );
+
+        // Eviction of the oldest entry leaves the cursor on the last one kept
+        history.set_retention(Retention {
+            max_entries: 1,
+            max_age: None,
+        });
+        assert_eq!(history.cursor(), 0);
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn test_table_marks_missing_entries() {
+        let table = render(&sample_history(), HistoryFormat::Table);
+        assert!(table.contains("/test/plain.jpg [missing] |"), "{}", table);
+    }
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    /// `count` entries one day apart, the most recent at `now`
+    fn dated_history(count: u64, now: u64) -> WallpaperHistory {
+        let mut history = WallpaperHistory::default();
+        history.retention.max_entries = 0;
+        for i in (0..count).rev() {
+            let mut entry = WallpaperEntry::new(
+                PathBuf::from(format!("/test/day{}.jpg", i)),
+                "scheme-tonal-spot".to_string(),
+                false,
+            );
+            entry.timestamp = now - i * DAY;
+            history.entries.push_front(entry);
+        }
+        history
+    }
+
+    fn days(history: &WallpaperHistory) -> Vec<String> {
+        history
+            .entries
+            .iter()
+            .map(|e| e.path.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_retention_count() {
+        let now = 100 * DAY;
+        let mut history = dated_history(10, now);
+        history.retention = Retention {
+            max_entries: 3,
+            max_age: None,
+        };
+
+        // Shrinking the limit trims every entry beyond it, not just one
+        assert_eq!(history.apply_retention(now), 7);
+        assert_eq!(days(&history), vec!["day0", "day1", "day2"]);
+    }
+
+    #[test]
+    fn test_retention_age() {
+        let now = 100 * DAY;
+        let mut history = dated_history(10, now);
+        history.retention = Retention {
+            max_entries: 0,
+            max_age: Some(Duration::from_secs(3 * DAY)),
+        };
+
+        history.apply_retention(now);
+        assert_eq!(days(&history), vec!["day0", "day1", "day2", "day3"]);
+    }
+
+    #[test]
+    fn test_retention_count_and_age() {
+        let now = 100 * DAY;
+        let mut history = dated_history(10, now);
+        history.retention = Retention {
+            max_entries: 2,
+            max_age: Some(Duration::from_secs(5 * DAY)),
+        };
+        history.apply_retention(now);
+        assert_eq!(days(&history), vec!["day0", "day1"]);
+
+        let mut history = dated_history(10, now);
+        history.retention = Retention {
+            max_entries: 8,
+            max_age: Some(Duration::from_secs(DAY)),
+        };
+        history.apply_retention(now);
+        assert_eq!(days(&history), vec!["day0", "day1"]);
+    }
+
+    #[test]
+    fn test_retention_unlimited() {
+        let now = 100 * DAY;
+        let mut history = dated_history(80, now);
+        history.retention = Retention {
+            max_entries: 0,
+            max_age: None,
+        };
+
+        assert_eq!(history.apply_retention(now), 0);
+        assert_eq!(history.len(), 80);
+    }
+
+    #[test]
+    fn test_retention_exempts_starred_and_most_recent() {
+        let now = 100 * DAY;
+        let mut history = dated_history(6, now);
+        history.set_starred(5, true).unwrap(); // day4
+        // The most recent entry is old too, e.g. nothing was set for a while
+        let later = now + 30 * DAY;
+        history.retention = Retention {
+            max_entries: 2,
+            max_age: Some(Duration::from_secs(7 * DAY)),
+        };
+
+        history.apply_retention(later);
+        assert_eq!(days(&history), vec!["day0", "day4"]);
+    }
+
+    #[test]
+    fn test_push_entry_applies_retention() {
+        let mut history = WallpaperHistory::default();
+        history.set_retention(Retention {
+            max_entries: 2,
+            max_age: Some(Duration::from_secs(DAY)),
+        });
+
+        let mut old = WallpaperEntry::new(
+            PathBuf::from("/test/old.jpg"),
+            "scheme-tonal-spot".to_string(),
+            false,
+        );
+        old.timestamp = unix_now() - 2 * DAY;
+        history.push_entry(old);
+        assert_eq!(history.len(), 1);
+
+        history.add_entry(
+            PathBuf::from("/test/new.jpg"),
+            "scheme-tonal-spot".to_string(),
+            false,
+        );
+        assert_eq!(days(&history), vec!["new"]);
+    }
+
+    fn write_raw(path: &Path, history: &WallpaperHistory) {
+        fs::write(path, serde_json::to_string(history).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_write_keeps_backup_of_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+
+        let first = history_with(1);
+        first.write_at(&path).unwrap();
+        assert!(!with_suffix(&path, BACKUP_SUFFIX).exists());
+
+        history_with(2).write_at(&path).unwrap();
+        let backup = WallpaperHistory::read_at(&with_suffix(&path, BACKUP_SUFFIX)).unwrap();
+        assert_eq!(backup.len(), 1);
+        assert_eq!(WallpaperHistory::read_at(&path).unwrap().len(), 2);
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(
+            names
+                .iter()
+                .all(|name| !name.to_string_lossy().ends_with(".tmp"))
+        );
+    }
+
+    #[test]
+    fn test_corrupt_file_is_recovered_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        write_raw(&with_suffix(&path, BACKUP_SUFFIX), &history_with(3));
+        // A write interrupted half way
+        fs::write(&path, r#"{"entries":[{"path":"/test/ima"#).unwrap();
+
+        let history = WallpaperHistory::read_at(&path).unwrap();
+        assert_eq!(history.len(), 3);
+
+        // Saving replaces the corrupt file but keeps both the good backup and the corrupt copy
+        history.write_at(&path).unwrap();
+        assert_eq!(WallpaperHistory::read_at(&path).unwrap().len(), 3);
+        assert_eq!(
+            WallpaperHistory::read_at(&with_suffix(&path, BACKUP_SUFFIX))
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(
+            fs::read_to_string(with_suffix(&path, CORRUPT_SUFFIX))
+                .unwrap()
+                .starts_with(r#"{"entries""#)
+        );
+    }
+
+    #[test]
+    fn test_corrupt_file_without_backup_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        fs::write(&path, "not json").unwrap();
+
+        let err = format!("{:#}", WallpaperHistory::read_at(&path).unwrap_err());
+        assert!(err.contains("no usable backup"), "{}", err);
+    }
+
+    #[test]
+    fn test_concurrent_updates_do_not_lose_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let config = HistoryConfig {
+            max_entries: 0,
+            ..Default::default()
+        };
+
+        std::thread::scope(|scope| {
+            for writer in 0..4 {
+                let (path, config) = (&path, &config);
+                scope.spawn(move || {
+                    for i in 0..10 {
+                        WallpaperHistory::update_at(path, config, |history| {
+                            history.add_entry(
+                                PathBuf::from(format!("/test/{}-{}.jpg", writer, i)),
+                                "scheme-tonal-spot".to_string(),
+                                false,
+                            );
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(WallpaperHistory::read_at(&path).unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_update_persists_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        write_raw(&path, &history_with(10));
+
+        let config = HistoryConfig {
+            max_entries: 4,
+            ..Default::default()
+        };
+        WallpaperHistory::update_at(&path, &config, |_| Ok(())).unwrap();
+
+        assert_eq!(WallpaperHistory::read_at(&path).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_load_prunes_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let mut history = history_with_files(dir.path(), 2);
+        history.add_entry(
+            dir.path().join("gone.jpg"),
+            "scheme-tonal-spot".to_string(),
+            false,
+        );
+        history.write_at(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+
+        let config = HistoryConfig {
+            auto_prune: true,
+            ..Default::default()
+        };
+        assert_eq!(WallpaperHistory::load_at(&path, &config).unwrap().len(), 2);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+        assert!(!dir.path().join("history.json.lock").exists());
+    }
+
+    const FIXTURE_V1: &str = include_str!("../tests/fixtures/history/v1.json");
+    const FIXTURE_V1_EXTENDED: &str = include_str!("../tests/fixtures/history/v1-extended.json");
+    const FIXTURE_V2: &str = include_str!("../tests/fixtures/history/v2.json");
+    const FIXTURE_V3: &str = include_str!("../tests/fixtures/history/v3.json");
+
+    #[test]
+    fn test_migrations_cover_every_version() {
+        assert_eq!(MIGRATIONS.len() as u64, HISTORY_VERSION - 1);
+    }
+
+    #[test]
+    fn test_load_fixture_v1() {
+        let history = WallpaperHistory::from_json(FIXTURE_V1).unwrap();
+
+        assert_eq!(history.len(), 2);
+        let entry = history.get_entry(0).unwrap();
+        assert_eq!(
+            entry.path,
+            PathBuf::from("/home/user/Pictures/walls/forest.jpg")
+        );
+        assert_eq!(entry.timestamp, 1_754_000_000);
+        assert_eq!(entry.matugen_type, "scheme-tonal-spot");
+        assert!(!entry.is_light);
+        assert!(entry.output.is_none());
+        assert!(!entry.starred);
+        assert!(entry.image.is_none());
+        assert!(history.get_entry(1).unwrap().is_light);
+    }
+
+    #[test]
+    fn test_load_fixture_v1_extended() {
+        let history = WallpaperHistory::from_json(FIXTURE_V1_EXTENDED).unwrap();
+
+        let entry = history.get_entry(0).unwrap();
+        assert_eq!(entry.output.as_deref(), Some("DP-1"));
+        assert!(entry.starred);
+        assert_eq!(entry.image.unwrap().to_string(), "jpeg 2560x1440");
+    }
+
+    #[test]
+    fn test_load_fixture_v2() {
+        let history = WallpaperHistory::from_json(FIXTURE_V2).unwrap();
+        let migrated = WallpaperHistory::from_json(FIXTURE_V1_EXTENDED).unwrap();
+
+        assert_eq!(history.to_json().unwrap(), migrated.to_json().unwrap());
+        assert_eq!(history.cursor(), 0);
+    }
+
+    #[test]
+    fn test_load_fixture_v3() {
+        let history = WallpaperHistory::from_json(FIXTURE_V3).unwrap();
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.cursor(), 1);
+        assert_eq!(
+            history.current().unwrap().path,
+            PathBuf::from("/home/user/Pictures/walls/lake.png")
+        );
+    }
+
+    #[test]
+    fn test_saved_history_has_current_version() {
+        let history = WallpaperHistory::from_json(FIXTURE_V1).unwrap();
+        let json = history.to_json().unwrap();
+
+        assert_eq!(stored_version(&json), Some(HISTORY_VERSION));
+        assert_eq!(WallpaperHistory::from_json(&json).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_reject_unknown_versions() {
+        assert!(WallpaperHistory::from_json(r#"{"version":0,"entries":[]}"#).is_err());
+        assert!(WallpaperHistory::from_json(r#"{"version":"2","entries":[]}"#).is_err());
+        assert!(WallpaperHistory::from_json(r#"{"version":99,"entries":[]}"#).is_err());
+        assert!(WallpaperHistory::from_json(r#"[]"#).is_err());
+    }
+
+    #[test]
+    fn test_newer_version_is_not_treated_as_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        write_raw(&with_suffix(&path, BACKUP_SUFFIX), &history_with(3));
+        fs::write(&path, r#"{"version":99,"entries":[],"new_field":true}"#).unwrap();
+
+        let err = WallpaperHistory::read_at(&path).unwrap_err().to_string();
+        assert!(err.contains("version 99"), "{}", err);
+    }
+
+    #[test]
+    fn test_find_favorite() {
+        let mut history = WallpaperHistory::default();
+        for name in ["forest.jpg", "forest-night.png", "lake.webp", "city.jpg"] {
+            let path = PathBuf::from("/walls").join(name);
+            history.add_entry(path, "scheme-tonal-spot".to_string(), false);
+        }
+        for step in 2..=4 {
+            history.set_starred(step, true).unwrap();
+        }
+
+        let path = |name: &str| history.find_favorite(name).map(|e| e.path.clone());
+
+        // Exact stem wins over the substring match on forest-night
+        assert_eq!(path("forest").unwrap(), PathBuf::from("/walls/forest.jpg"));
+        assert_eq!(path("LAKE").unwrap(), PathBuf::from("/walls/lake.webp"));
+        assert_eq!(
+            path("/walls/lake.webp").unwrap(),
+            PathBuf::from("/walls/lake.webp")
+        );
+        // city.jpg is not starred
+        assert!(path("city").is_err());
+        // Ambiguous
+        assert!(path("o").is_err());
+    }
+
+    #[test]
+    fn test_find_favorite_without_stars() {
+        let history = history_with(2);
+        let err = history.find_favorite("image0").unwrap_err().to_string();
+        assert!(err.contains("No starred wallpapers"), "{}", err);
+    }
+
+    #[test]
+    fn test_entry_image_info_roundtrip() {
+        let info = ImageInfo {
+            format: crate::images::ImageFormat::Png,
+            width: 2560,
+            height: 1440,
+        };
+        let entry = WallpaperEntry::new(
+            PathBuf::from("/test/image.png"),
+            "scheme-content".to_string(),
+            false,
+        )
+        .with_image(info);
+
+        let json = serde_json::to_string(&entry).unwrap();
+        let parsed: WallpaperEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.image, Some(info));
+    }
+
+    #[test]
+    fn test_recent_paths() {
+        let mut history = WallpaperHistory::default();
+        for i in 0..5 {
+            let path = PathBuf::from(format!("/test/image{}.jpg", i));
+            history.add_entry(path, "scheme-tonal-spot".to_string(), false);
+        }
+
+        assert_eq!(
+            history.recent_paths(2),
+            vec![
+                PathBuf::from("/test/image4.jpg"),
+                PathBuf::from("/test/image3.jpg")
+            ]
+        );
+        assert_eq!(history.recent_paths(10).len(), 5);
+    }
+
     #[test]
     fn test_history_max_entries() {
         let mut history = WallpaperHistory::default();
@@ -263,4 +1843,114 @@ mod tests {
         assert_eq!(deserialized.len(), 1);
         assert_eq!(deserialized.get_entry(0).unwrap().path, path);
     }
+
+    fn sample_history() -> WallpaperHistory {
+        let mut history = WallpaperHistory::default();
+        let mut plain = WallpaperEntry::new(
+            PathBuf::from("/test/plain.jpg"),
+            "scheme-content".to_string(),
+            true,
+        );
+        plain.timestamp = 0;
+        history.push_entry(plain);
+
+        let mut detailed = WallpaperEntry::new(
+            PathBuf::from("/test/a, b.png"),
+            "scheme-tonal-spot".to_string(),
+            false,
+        )
+        .with_output(Some("DP-1".to_string()))
+        .with_image(ImageInfo {
+            format: crate::images::ImageFormat::Png,
+            width: 1920,
+            height: 1080,
+        });
+        detailed.timestamp = 1_700_000_000;
+        history.push_entry(detailed);
+
+        history
+    }
+
+    fn render(history: &WallpaperHistory, format: HistoryFormat) -> String {
+        let mut tags = TagStore::default();
+        tags.add(
+            Path::new("/test/a, b.png"),
+            &["nature".to_string(), "dark".to_string()],
+        )
+        .unwrap();
+
+        let entries = history.filtered(&HistoryFilter::default(), &tags);
+        let mut out = Vec::new();
+        write_history(&entries, &tags, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_history_json() {
+        let json = render(&sample_history(), HistoryFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(
+            value[0],
+            serde_json::json!({
+                "index": 1,
+                "path": "/test/a, b.png",
+                "timestamp": 1_700_000_000,
+                "time": "2023-11-14T22:13:20Z",
+                "matugen_type": "scheme-tonal-spot",
+                "is_light": false,
+                "output": "DP-1",
+                "starred": false,
+                "missing": true,
+                "format": "png",
+                "width": 1920,
+                "height": 1080,
+                "tags": ["dark", "nature"]
+            })
+        );
+        assert_eq!(value[1]["time"], "1970-01-01T00:00:00Z");
+        assert!(value[1]["output"].is_null());
+        assert!(value[1]["format"].is_null());
+        assert_eq!(value[1]["tags"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_history_ndjson() {
+        let ndjson = render(&sample_history(), HistoryFormat::Ndjson);
+        let lines: Vec<&str> = ndjson.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        for (i, line) in lines.iter().enumerate() {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["index"], i + 1);
+        }
+    }
+
+    #[test]
+    fn test_history_csv() {
+        let csv = render(&sample_history(), HistoryFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "index,path,timestamp,time,matugen_type,is_light,output,starred,missing,format,width,height,tags",
+                "1,\"/test/a, b.png\",1700000000,2023-11-14T22:13:20Z,scheme-tonal-spot,false,DP-1,false,true,png,1920,1080,dark;nature",
+                "2,/test/plain.jpg,0,1970-01-01T00:00:00Z,scheme-content,true,,false,true,,,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_empty_machine_formats() {
+        let history = WallpaperHistory::default();
+
+        assert_eq!(render(&history, HistoryFormat::Json).trim(), "[]");
+        assert_eq!(render(&history, HistoryFormat::Ndjson), "");
+        assert_eq!(render(&history, HistoryFormat::Csv).lines().count(), 1);
+        assert_eq!(
+            render(&history, HistoryFormat::Table).trim(),
+            "No wallpaper history found."
+        );
+    }
 }
@@ -0,0 +1,271 @@
+use anyhow::{Context, Result, anyhow};
+use imagesize::{ImageError, ImageType};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::normalize_and_check_path;
+
+/// Image formats accepted as wallpapers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+    Avif,
+}
+
+impl ImageFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Png => "png",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Avif => "avif",
+        }
+    }
+
+    fn from_image_type(image_type: ImageType) -> Option<Self> {
+        match image_type {
+            ImageType::Jpeg => Some(ImageFormat::Jpeg),
+            ImageType::Png => Some(ImageFormat::Png),
+            ImageType::Gif => Some(ImageFormat::Gif),
+            ImageType::Webp => Some(ImageFormat::Webp),
+            ImageType::Bmp => Some(ImageFormat::Bmp),
+            ImageType::Tiff => Some(ImageFormat::Tiff),
+            ImageType::Heif(imagesize::Compression::Av1) => Some(ImageFormat::Avif),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Format and dimensions read from the image header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for ImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}x{}", self.format, self.width, self.height)
+    }
+}
+
+/// A wallpaper that exists and has a readable image header
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub path: PathBuf,
+    pub info: ImageInfo,
+}
+
+impl Image {
+    /// Resolve `original` and check its content, so that no external program
+    /// is ever handed an empty, truncated or non-image file
+    pub fn open(original: &Path) -> Result<Self> {
+        let path = normalize_and_check_path(original)?;
+        let info = inspect_image(&path)?;
+        Ok(Self { path, info })
+    }
+}
+
+/// Detect the format by magic bytes and decode the header for the dimensions
+pub fn inspect_image(path: &Path) -> Result<ImageInfo> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open image: {}", path.display()))?;
+
+    let len = file
+        .metadata()
+        .with_context(|| format!("Failed to read image metadata: {}", path.display()))?
+        .len();
+    if len == 0 {
+        return Err(anyhow!("Image file is empty: {}", path.display()));
+    }
+
+    let mut reader = BufReader::new(file);
+    let image_type = imagesize::reader_type(&mut reader).map_err(|e| match e {
+        ImageError::NotSupported | ImageError::CorruptedImage => not_an_image(path),
+        // Files shorter than any magic number fail with an unexpected EOF
+        ImageError::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            not_an_image(path)
+        }
+        ImageError::IoError(e) => {
+            anyhow!(e).context(format!("Failed to read image: {}", path.display()))
+        }
+    })?;
+
+    let format = ImageFormat::from_image_type(image_type).ok_or_else(|| not_an_image(path))?;
+
+    let size = image_type.reader_size(&mut reader).map_err(|e| {
+        anyhow!(
+            "Failed to decode {} header of {}: {}. The file is probably truncated or corrupt.",
+            format,
+            path.display(),
+            e
+        )
+    })?;
+
+    let width = u32::try_from(size.width).unwrap_or(u32::MAX);
+    let height = u32::try_from(size.height).unwrap_or(u32::MAX);
+    if width == 0 || height == 0 {
+        return Err(anyhow!(
+            "Image has invalid dimensions {}x{}: {}",
+            width,
+            height,
+            path.display()
+        ));
+    }
+
+    Ok(ImageInfo {
+        format,
+        width,
+        height,
+    })
+}
+
+fn not_an_image(path: &Path) -> anyhow::Error {
+    anyhow!(
+        "Not a supported image (jpeg, png, gif, webp, bmp, tiff, avif): {}",
+        path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Signature and IHDR chunk of a 123x321 PNG
+    const PNG_HEADER: &[u8] = &[
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x7B, 0x00, 0x00, 0x01, 0x41, 0x08, 0x06, 0x00, 0x00, 0x00, 0x9A,
+        0x38, 0xC4,
+    ];
+
+    /// SOI, an APP0 segment and a baseline SOF0 for a 640x480 JPEG
+    const JPEG_HEADER: &[u8] = &[
+        0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00, 0x01, 0x01, 0x00, 0x00,
+        0x01, 0x00, 0x01, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x03,
+        0x01, 0x22, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01,
+    ];
+
+    fn write_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_inspect_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "a.png", PNG_HEADER);
+
+        let info = inspect_image(&path).unwrap();
+        assert_eq!(info.format, ImageFormat::Png);
+        assert_eq!((info.width, info.height), (123, 321));
+        assert_eq!(info.to_string(), "png 123x321");
+    }
+
+    #[test]
+    fn test_inspect_jpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "a.jpg", JPEG_HEADER);
+
+        let info = inspect_image(&path).unwrap();
+        assert_eq!(info.format, ImageFormat::Jpeg);
+        assert_eq!((info.width, info.height), (640, 480));
+    }
+
+    #[test]
+    fn test_format_is_sniffed_not_taken_from_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "actually-png.jpg", PNG_HEADER);
+
+        assert_eq!(inspect_image(&path).unwrap().format, ImageFormat::Png);
+    }
+
+    #[test]
+    fn test_reject_empty_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_image.jpg");
+        let err = inspect_image(&path).unwrap_err().to_string();
+        assert!(err.contains("empty"), "{}", err);
+    }
+
+    #[test]
+    fn test_reject_non_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "notes.jpg", b"just some text, not an image");
+
+        let err = inspect_image(&path).unwrap_err().to_string();
+        assert!(err.contains("Not a supported image"), "{}", err);
+    }
+
+    #[test]
+    fn test_reject_tiny_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "tiny.png", b"\x89P");
+
+        assert!(inspect_image(&path).is_err());
+    }
+
+    #[test]
+    fn test_reject_truncated_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = write_file(dir.path(), "cut.png", &PNG_HEADER[..18]);
+        let jpeg = write_file(dir.path(), "cut.jpg", &JPEG_HEADER[..24]);
+
+        for path in [png, jpeg] {
+            let err = inspect_image(&path).unwrap_err().to_string();
+            assert!(err.contains("truncated"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_reject_zero_dimensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut header = PNG_HEADER.to_vec();
+        header[16..20].copy_from_slice(&[0, 0, 0, 0]);
+        let path = write_file(dir.path(), "zero.png", &header);
+
+        let err = inspect_image(&path).unwrap_err().to_string();
+        assert!(err.contains("invalid dimensions"), "{}", err);
+    }
+
+    #[test]
+    fn test_image_open_checks_path_first() {
+        let err = Image::open(Path::new("/nonexistent/file.jpg"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("does not exist"), "{}", err);
+    }
+
+    #[test]
+    fn test_image_info_serde() {
+        let info = ImageInfo {
+            format: ImageFormat::Webp,
+            width: 1920,
+            height: 1080,
+        };
+        let json = serde_json::to_string(&info).unwrap();
+        assert_eq!(json, r#"{"format":"webp","width":1920,"height":1080}"#);
+        assert_eq!(serde_json::from_str::<ImageInfo>(&json).unwrap(), info);
+    }
+}
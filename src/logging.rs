@@ -0,0 +1,306 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU8, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::config::LogConfig;
+use crate::history::get_data_directory;
+use crate::utils::{ProgramOutput, with_suffix};
+
+const LOG_FILENAME: &str = "wallpaper.log";
+
+/// How much is printed to the terminal. The log file always gets everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// `-q`: only warnings, errors and the output a command was asked for
+    Quiet,
+    /// Commands being run and the output of the programs
+    Normal,
+    /// `-v`: also exit status and duration of every program
+    Verbose,
+    /// `-vv`: also file locations and how every pipeline step is configured
+    Debug,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static LOG: Mutex<Option<LogFile>> = Mutex::new(None);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of `level` are printed
+pub fn enabled(level: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Print to stdout unless `-q` was given
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Verbosity::Normal) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print to stderr with `-v` or more
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Verbosity::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Print to stderr with `-vv`
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Verbosity::Debug) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Print a warning to stderr, whatever the verbosity, and record it in the log
+macro_rules! warning {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        eprintln!("warning: {}", message);
+        $crate::logging::record(&format!("warning: {}", message));
+    }};
+}
+
+pub(crate) use {debug, info, verbose, warning};
+
+pub fn get_log_file_path() -> Result<PathBuf> {
+    let data_dir = get_data_directory()?;
+    Ok(data_dir.join(LOG_FILENAME))
+}
+
+/// Start appending to the log file, unless disabled in config.toml
+pub fn open(config: &LogConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let path = get_log_file_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let file = open_append(&path)
+        .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+
+    debug!("Log file: {}", path.display());
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some(LogFile {
+        path,
+        file,
+        max_size: config.max_size,
+        keep: config.keep,
+    });
+    Ok(())
+}
+
+/// Append a timestamped message to the log file. Later lines of a multi-line
+/// message are indented. Does nothing when the log is not open.
+pub fn record(message: &str) {
+    let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(file) = log.as_mut() else {
+        return;
+    };
+
+    let text = format_record(SystemTime::now(), std::process::id(), message);
+    if let Err(e) = file.append(&text) {
+        // Logging must never break setting the wallpaper; give up on it for this run
+        eprintln!(
+            "warning: failed to write log file {}: {}",
+            file.path.display(),
+            e
+        );
+        *log = None;
+    }
+}
+
+/// Record a finished (or stopped) program with its captured output
+pub fn record_program(command: &str, result: &Result<ProgramOutput>, elapsed: Duration) {
+    let elapsed = humantime::format_duration(round_to_millis(elapsed));
+    let mut message = match result {
+        Ok(output) => format!("ran `{}`: {} in {}", command, output.status, elapsed),
+        Err(e) => format!("ran `{}`: {:#} after {}", command, e, elapsed),
+    };
+    if let Ok(output) = result {
+        for (name, text) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            for line in text.lines() {
+                message.push_str(&format!("\n{}| {}", name, line));
+            }
+        }
+    }
+    record(&message);
+}
+
+/// Record how the run ended
+pub fn record_exit(error: Option<&anyhow::Error>, code: i32, elapsed: Duration) {
+    let elapsed = humantime::format_duration(round_to_millis(elapsed));
+    match error {
+        Some(e) => record(&format!("exit code {} after {}: {:?}", code, elapsed, e)),
+        None => record(&format!("exit code {} after {}", code, elapsed)),
+    }
+}
+
+pub fn round_to_millis(duration: Duration) -> Duration {
+    Duration::from_millis(duration.as_millis() as u64)
+}
+
+fn format_record(time: SystemTime, pid: u32, message: &str) -> String {
+    let mut text = format!("{} [{}]", humantime::format_rfc3339_seconds(time), pid);
+    for (index, line) in message.lines().enumerate() {
+        text.push_str(if index == 0 { " " } else { "\n    " });
+        text.push_str(line);
+    }
+    text.push('\n');
+    text
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    max_size: u64,
+    keep: usize,
+}
+
+impl LogFile {
+    /// Write `text` in one go, so records of concurrent runs do not interleave
+    fn append(&mut self, text: &str) -> io::Result<()> {
+        if self.max_size > 0 && self.file.metadata()?.len() >= self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(text.as_bytes())
+    }
+
+    /// wallpaper.log becomes wallpaper.log.1, .1 becomes .2 and so on; the
+    /// oldest beyond `keep` is overwritten or, with `keep = 0`, removed
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| with_suffix(&self.path, &format!(".{}", n));
+        if self.keep == 0 {
+            ignore_missing(fs::remove_file(&self.path))?;
+        } else {
+            for n in (1..self.keep).rev() {
+                ignore_missing(fs::rename(rotated(n), rotated(n + 1)))?;
+            }
+            ignore_missing(fs::rename(&self.path, rotated(1)))?;
+        }
+        self.file = open_append(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Another run may have rotated the log already
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn log_file(dir: &TempDir, max_size: u64, keep: usize) -> LogFile {
+        let path = dir.path().join(LOG_FILENAME);
+        LogFile {
+            file: open_append(&path).unwrap(),
+            path,
+            max_size,
+            keep,
+        }
+    }
+
+    #[test]
+    fn test_verbosity_from_flags() {
+        assert_eq!(Verbosity::from_flags(false, 0), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(false, 1), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(false, 2), Verbosity::Debug);
+        assert_eq!(Verbosity::from_flags(false, 5), Verbosity::Debug);
+        assert_eq!(Verbosity::from_flags(true, 0), Verbosity::Quiet);
+        assert!(Verbosity::Quiet < Verbosity::Normal);
+        assert!(Verbosity::Verbose < Verbosity::Debug);
+    }
+
+    #[test]
+    fn test_format_record() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            format_record(time, 42, "ran `true`: exit status: 0 in 3ms"),
+            "2023-11-14T22:13:20Z [42] ran `true`: exit status: 0 in 3ms\n"
+        );
+        assert_eq!(
+            format_record(time, 42, "ran `wallust`\nstdout| [I] image: a.png"),
+            "2023-11-14T22:13:20Z [42] ran `wallust`\n    stdout| [I] image: a.png\n"
+        );
+    }
+
+    #[test]
+    fn test_log_appends() {
+        let dir = TempDir::new().unwrap();
+        let mut log = log_file(&dir, 0, 3);
+        log.append("one\n").unwrap();
+        log.append("two\n").unwrap();
+
+        let mut log = log_file(&dir, 0, 3);
+        log.append("three\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&log.path).unwrap(), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_log_rotation() {
+        let dir = TempDir::new().unwrap();
+        let mut log = log_file(&dir, 10, 2);
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).ok();
+
+        for record in ["first run\n", "second run\n", "third run\n", "fourth run\n"] {
+            log.append(record).unwrap();
+        }
+
+        assert_eq!(read("wallpaper.log").as_deref(), Some("fourth run\n"));
+        assert_eq!(read("wallpaper.log.1").as_deref(), Some("third run\n"));
+        assert_eq!(read("wallpaper.log.2").as_deref(), Some("second run\n"));
+        assert_eq!(read("wallpaper.log.3"), None);
+    }
+
+    #[test]
+    fn test_log_rotation_without_keep() {
+        let dir = TempDir::new().unwrap();
+        let mut log = log_file(&dir, 10, 0);
+
+        log.append("first run\n").unwrap();
+        log.append("second run\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&log.path).unwrap(), "second run\n");
+        assert!(!dir.path().join("wallpaper.log.1").exists());
+    }
+}
@@ -1,16 +1,31 @@
 use anyhow::{Result, anyhow};
 use clap::{CommandFactory, FromArgMatches};
+use std::time::Instant;
 
+mod backends;
 mod cli;
+mod commands;
+mod config;
+mod daemon;
+mod generated;
+mod generators;
 mod history;
+mod images;
+mod logging;
+mod outputs;
+mod palette;
+mod pipeline;
 mod programs;
+mod random;
+mod signals;
+mod tags;
+mod templates;
 mod utils;
 
-use cli::Cli;
-use history::{add_to_history, display_history, restore_from_history};
-use programs::{exec_matugen_with_dry_run, exec_swww_with_dry_run, exec_wal_with_dry_run};
-
-use utils::normalize_and_check_path;
+use cli::{Cli, Command};
+use commands::{App, PARTIAL_SUCCESS_EXIT_CODE};
+use config::Config;
+use logging::{Verbosity, debug, warning};
 
 fn main() -> Result<()> {
     let version = "v0.5 - code base rewrite + validation + arg parsing fixed";
@@ -24,68 +39,79 @@ fn main() -> Result<()> {
     let matches = cmd.get_matches();
     let cli = Cli::from_arg_matches(&matches).map_err(|e| anyhow!(e.to_string()))?;
 
-    // Validation: exactly one main option must be provided (dry-run is a modifier)
-    let main_options_count = cli.gui as u8
-        + cli.path.is_some() as u8
-        + cli.history as u8
-        + (cli.restore_step != 0) as u8;
-
-    if main_options_count != 1 {
-        return Err(anyhow!(
-            "Exactly one of --gui, path, --history, or --restore must be provided."
-        ));
-    }
+    logging::set_verbosity(Verbosity::from_flags(cli.quiet, cli.verbose));
+    let started = Instant::now();
+    let invocation: Vec<String> = std::env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    let dry_run = cli.dry_run;
+    let history_size = cli.history_size;
+    let history_max_age = cli.history_max_age;
+    let command = cli.into_command()?;
+    let is_daemon = matches!(command, Command::Daemon(_));
+
+    // History and current only read the data directory; do not fail on a broken config
+    let config = match command {
+        Command::History(_) | Command::Current => Ok(Config::load().unwrap_or_else(|e| {
+            warning!("ignoring config file: {:#}", e);
+            Config::default()
+        })),
+        _ => Config::load(),
+    };
 
-    if cli.history {
-        return display_history();
+    // Opened even when the config is broken, so that failure is recorded too
+    let log_config = config
+        .as_ref()
+        .map(|config| config.log.clone())
+        .unwrap_or_default();
+    if let Err(e) = logging::open(&log_config) {
+        warning!("not writing the log file: {:#}", e);
     }
+    logging::record(&format!("start: {}", invocation.join(" ")));
 
-    if cli.restore_step != 0 {
-        let entry = restore_from_history(cli.restore_step)?;
-        println!(
-            "Restoring wallpaper from step {}: {}",
-            cli.restore_step,
-            entry.path.display()
-        );
-
-        let path = normalize_and_check_path(&entry.path)?;
-
-        exec_swww_with_dry_run(&path, cli.dry_run)?;
-        exec_matugen_with_dry_run(&path, &entry.matugen_type, cli.dry_run)?;
-        exec_wal_with_dry_run(&path, entry.is_light, cli.dry_run)?;
-
-        // Add restored wallpaper to history as most recent
-        if !cli.dry_run {
-            add_to_history(&path, &entry.matugen_type, entry.is_light)?;
+    let result = config.and_then(|mut config| {
+        if let Ok(path) = config::get_config_file_path() {
+            debug!("Config file: {}", path.display());
         }
 
-        println!("Wallpaper restored successfully.");
-        return Ok(());
-    }
+        if let Some(size) = history_size {
+            config.history.max_entries = size;
+        }
+        if let Some(max_age) = history_max_age {
+            config.history.max_age = Some(max_age);
+        }
 
-    let raw_path = if cli.gui {
-        rfd::FileDialog::new()
-            .set_title("Choose wallpaper")
-            .pick_file()
-            .ok_or_else(|| anyhow!("No file selected via GUI"))?
-    } else {
-        cli.path
-            .ok_or_else(|| anyhow!("No path provided. Supply a path."))?
-    };
+        let app = App::new(version, config, dry_run);
 
-    let path = normalize_and_check_path(&raw_path)?;
+        // Stop running programs on Ctrl+C/SIGTERM instead of leaving them behind
+        signals::install()?;
 
-    println!("wallpaper {} - {}", version, path.display());
+        commands::run(command, &app)?;
+        Ok(app.partial.get())
+    });
 
-    exec_swww_with_dry_run(&path, cli.dry_run)?;
-    exec_matugen_with_dry_run(&path, &cli.matugen_type, cli.dry_run)?;
-    exec_wal_with_dry_run(&path, cli.light, cli.dry_run)?;
+    let signal = signals::received();
+    let code = match (&result, signal) {
+        // SIGTERM is how a service manager stops the slideshow
+        (Ok(_), Some(libc::SIGTERM)) if is_daemon => 0,
+        (_, Some(signal)) => 128 + signal,
+        (Err(_), None) => 1,
+        (Ok(true), None) => PARTIAL_SUCCESS_EXIT_CODE,
+        (Ok(false), None) => 0,
+    };
+    logging::record_exit(result.as_ref().err(), code, started.elapsed());
 
-    // Add to history (only if not dry run)
-    if !cli.dry_run {
-        add_to_history(&path, &cli.matugen_type, cli.light)?;
+    if code != 0 && signal.is_some() {
+        if let Err(e) = result {
+            eprintln!("Error: {:?}", e);
+        }
+        std::process::exit(code);
     }
+    result?;
 
-    println!("Done.");
+    if code != 0 {
+        std::process::exit(code);
+    }
     Ok(())
 }
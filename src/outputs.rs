@@ -0,0 +1,125 @@
+use anyhow::{Context, Result, anyhow};
+use std::{path::PathBuf, process::Command, str::FromStr};
+
+/// Value of `--output`: either a bare output name (`DP-1`) that targets the
+/// image given on the command line, or `DP-1=/path/to/image.jpg` for per-output mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputArg {
+    pub name: String,
+    pub path: Option<PathBuf>,
+}
+
+impl FromStr for OutputArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = match s.split_once('=') {
+            Some((name, "")) => {
+                return Err(format!("missing image path after `{}=`", name));
+            }
+            Some((name, path)) => (name, Some(PathBuf::from(path))),
+            None => (s, None),
+        };
+
+        if name.is_empty() {
+            return Err("output name must not be empty".to_string());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            path,
+        })
+    }
+}
+
+/// Names of the outputs known to the running swww daemon
+pub fn query_outputs() -> Result<Vec<String>> {
+    let output = Command::new("swww")
+        .arg("query")
+        .output()
+        .with_context(|| "Failed to run `swww query` (is swww installed and in PATH?)")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`swww query` exited with status: {} (is swww-daemon running?)",
+            output.status
+        ));
+    }
+
+    Ok(parse_swww_query(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse `swww query` output. Depending on the swww version a line looks like
+/// `DP-1: 1920x1080, scale: 1, currently displaying: image: /a.jpg`
+/// or the same with a leading `: ` (namespace separator).
+pub fn parse_swww_query(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start().trim_start_matches(':').trim_start();
+            let (name, _) = line.split_once(':')?;
+            let name = name.trim();
+            (!name.is_empty()).then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// Fail with a clear message if any requested output is not reported by swww
+pub fn check_outputs_exist<'a>(
+    requested: impl IntoIterator<Item = &'a str>,
+    available: &[String],
+) -> Result<()> {
+    for name in requested {
+        if !available.iter().any(|a| a == name) {
+            return Err(anyhow!(
+                "Unknown output: {}. Available outputs: {}",
+                name,
+                available.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_arg() {
+        let bare: OutputArg = "DP-1".parse().unwrap();
+        assert_eq!(bare.name, "DP-1");
+        assert!(bare.path.is_none());
+
+        let assigned: OutputArg = "HDMI-A-1=/test/image.jpg".parse().unwrap();
+        assert_eq!(assigned.name, "HDMI-A-1");
+        assert_eq!(assigned.path, Some(PathBuf::from("/test/image.jpg")));
+
+        assert!("".parse::<OutputArg>().is_err());
+        assert!("=/test/image.jpg".parse::<OutputArg>().is_err());
+        assert!("DP-1=".parse::<OutputArg>().is_err());
+    }
+
+    #[test]
+    fn test_parse_swww_query() {
+        let output = "\
+DP-1: 2560x1440, scale: 1, currently displaying: image: /home/user/a.jpg
+HDMI-A-1: 1920x1080, scale: 1, currently displaying: color: 000000
+";
+        assert_eq!(parse_swww_query(output), vec!["DP-1", "HDMI-A-1"]);
+    }
+
+    #[test]
+    fn test_parse_swww_query_with_namespace_prefix() {
+        let output = ": eDP-1: 1920x1200, scale: 1.25, currently displaying: image: /a.jpg\n\n";
+        assert_eq!(parse_swww_query(output), vec!["eDP-1"]);
+    }
+
+    #[test]
+    fn test_check_outputs_exist() {
+        let available = vec!["DP-1".to_string(), "HDMI-A-1".to_string()];
+
+        assert!(check_outputs_exist(["DP-1"], &available).is_ok());
+        assert!(check_outputs_exist(["DP-2"], &available).is_err());
+    }
+}
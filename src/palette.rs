@@ -0,0 +1,818 @@
+use anyhow::{Context, Result, anyhow};
+use image::{ImageFormat, ImageReader, imageops::FilterType};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::logging::{self, info, verbose};
+use crate::signals;
+use crate::utils::write_atomic;
+
+pub const PALETTE_FILENAME: &str = "palette.json";
+const COLORS_FILENAME: &str = "colors";
+
+/// Longest side of the copy the colors are taken from; more pixels barely change the result
+const SAMPLE_SIZE: u32 = 128;
+/// Number of k-means clusters the image is reduced to
+const CLUSTERS: usize = 16;
+const MAX_ITERATIONS: usize = 30;
+/// Fixed seed, so the same image always gives the same palette
+const SEED: u64 = 0x7761_6c6c;
+
+/// Clusters below this Oklab chroma count as grey
+const GREY_CHROMA: f32 = 0.04;
+/// How far (in degrees) an image color may be from an ANSI hue to stand in for it
+const HUE_TOLERANCE: f32 = 30.0;
+/// Oklab distance within which clusters count as shades of the same color
+const NEIGHBOUR_DISTANCE: f32 = 0.1;
+
+/// OkLCh hues of red, green, yellow, blue, magenta and cyan, in ANSI order
+const ANSI_HUES: [f32; 6] = [25.0, 145.0, 100.0, 260.0, 330.0, 195.0];
+
+/// An sRGB color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// `#rrggbb`
+    pub fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Parse `#rrggbb` or `rrggbb`
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Self::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    pub fn to_oklab(self) -> Oklab {
+        let [r, g, b] = [self.r, self.g, self.b].map(|c| srgb_to_linear(c as f32 / 255.0));
+
+        let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+        let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+        let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+        Oklab {
+            l: 0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+            a: 1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+            b: 0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+        }
+    }
+
+    /// Raise the Oklab lightness by `percent` points, keeping the hue and, as far as
+    /// sRGB allows, the chroma
+    pub fn lighten(self, percent: f32) -> Self {
+        let lab = self.to_oklab();
+        Oklab {
+            l: lab.l + percent / 100.0,
+            ..lab
+        }
+        .to_color()
+    }
+
+    pub fn darken(self, percent: f32) -> Self {
+        self.lighten(-percent)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.hex())
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.hex()
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Color::from_hex(&value)
+            .ok_or_else(|| format!("invalid color `{}`, expected #rrggbb", value))
+    }
+}
+
+/// A color in the Oklab perceptual color space, where equal distances look
+/// like equal differences
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Oklab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Oklab {
+    pub fn chroma(self) -> f32 {
+        self.a.hypot(self.b)
+    }
+
+    /// Hue angle in degrees, 0..360
+    pub fn hue(self) -> f32 {
+        self.b.atan2(self.a).to_degrees().rem_euclid(360.0)
+    }
+
+    pub fn from_lch(l: f32, chroma: f32, hue: f32) -> Self {
+        let (sin, cos) = hue.to_radians().sin_cos();
+        Self {
+            l,
+            a: chroma * cos,
+            b: chroma * sin,
+        }
+    }
+
+    fn distance_squared(self, other: Oklab) -> f32 {
+        (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+    }
+
+    fn to_linear_srgb(self) -> [f32; 3] {
+        let l = (self.l + 0.396_337_78 * self.a + 0.215_803_76 * self.b).powi(3);
+        let m = (self.l - 0.105_561_346 * self.a - 0.063_854_17 * self.b).powi(3);
+        let s = (self.l - 0.089_484_18 * self.a - 1.291_485_5 * self.b).powi(3);
+
+        [
+            4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+            -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+            -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+        ]
+    }
+
+    fn in_gamut(self) -> bool {
+        self.to_linear_srgb()
+            .iter()
+            .all(|c| (-0.0001..=1.0001).contains(c))
+    }
+
+    /// The sRGB color, with chroma reduced as far as needed to fit sRGB
+    pub fn to_color(self) -> Color {
+        // Black and white have no chroma to keep
+        if self.l <= 0.0 {
+            return Color::new(0, 0, 0);
+        }
+        if self.l >= 1.0 {
+            return Color::new(255, 255, 255);
+        }
+        let (l, chroma, hue) = (self.l, self.chroma(), self.hue());
+
+        let mut fitted = Oklab::from_lch(l, chroma, hue);
+        if !fitted.in_gamut() {
+            let (mut low, mut high) = (0.0, chroma);
+            for _ in 0..20 {
+                let mid = (low + high) / 2.0;
+                if Oklab::from_lch(l, mid, hue).in_gamut() {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            fitted = Oklab::from_lch(l, low, hue);
+        }
+
+        let [r, g, b] = fitted
+            .to_linear_srgb()
+            .map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8);
+        Color::new(r, g, b)
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A group of similar image colors and the share of pixels it covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cluster {
+    pub color: Oklab,
+    pub weight: f32,
+}
+
+/// Colors for one mode: the special colors and the 16 terminal colors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scheme {
+    pub background: Color,
+    pub foreground: Color,
+    pub cursor: Color,
+    pub accent: Color,
+    pub colors: [Color; 16],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Dark,
+    Light,
+}
+
+impl Mode {
+    pub fn from_light(is_light: bool) -> Self {
+        if is_light { Mode::Light } else { Mode::Dark }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Dark => "dark",
+            Mode::Light => "light",
+        }
+    }
+}
+
+/// Everything the native generator derives from a wallpaper, as stored in palette.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub wallpaper: PathBuf,
+    /// Which scheme is in use, from `--light`
+    pub mode: Mode,
+    pub dark: Scheme,
+    pub light: Scheme,
+}
+
+impl Palette {
+    pub fn from_clusters(wallpaper: &Path, clusters: &[Cluster], is_light: bool) -> Self {
+        Self {
+            wallpaper: wallpaper.to_path_buf(),
+            mode: Mode::from_light(is_light),
+            dark: derive_scheme(clusters, Mode::Dark),
+            light: derive_scheme(clusters, Mode::Light),
+        }
+    }
+
+    /// The scheme selected by `mode`
+    pub fn active(&self) -> &Scheme {
+        match self.mode {
+            Mode::Dark => &self.dark,
+            Mode::Light => &self.light,
+        }
+    }
+}
+
+/// Lightness and chroma targets of one mode
+struct Tones {
+    background: f32,
+    foreground: f32,
+    /// color7 (white) and color8 (bright black)
+    white: f32,
+    bright_black: f32,
+    normal: f32,
+    bright: f32,
+    accent: f32,
+    /// Largest chroma of background, foreground and greys
+    neutral_chroma: f32,
+}
+
+const DARK_TONES: Tones = Tones {
+    background: 0.18,
+    foreground: 0.92,
+    white: 0.82,
+    bright_black: 0.45,
+    normal: 0.72,
+    bright: 0.80,
+    accent: 0.75,
+    neutral_chroma: 0.03,
+};
+
+const LIGHT_TONES: Tones = Tones {
+    background: 0.97,
+    foreground: 0.25,
+    white: 0.35,
+    bright_black: 0.68,
+    normal: 0.52,
+    bright: 0.45,
+    accent: 0.55,
+    neutral_chroma: 0.02,
+};
+
+/// Build a terminal scheme around the image's colors: background and greys are
+/// tinted with the dominant color, the six ANSI colors take the hue of a nearby
+/// image color when there is one, so they stay recognizable as red, green, ...
+pub fn derive_scheme(clusters: &[Cluster], mode: Mode) -> Scheme {
+    let tones = match mode {
+        Mode::Dark => &DARK_TONES,
+        Mode::Light => &LIGHT_TONES,
+    };
+
+    // A gradient is split into several clusters; count their neighbours too
+    let area = |c: &Cluster| -> f32 {
+        clusters
+            .iter()
+            .filter(|other| other.color.distance_squared(c.color) <= NEIGHBOUR_DISTANCE.powi(2))
+            .map(|other| other.weight)
+            .sum()
+    };
+    let dominant = clusters
+        .iter()
+        .max_by(|x, y| area(x).total_cmp(&area(y)))
+        .map(|c| c.color)
+        .unwrap_or_default();
+    let accent = clusters
+        .iter()
+        .max_by(|x, y| {
+            let score = |c: &Cluster| c.color.chroma() * c.weight.sqrt();
+            score(x).total_cmp(&score(y))
+        })
+        .map(|c| c.color)
+        .unwrap_or_default();
+
+    let neutral = |l: f32| {
+        Oklab::from_lch(
+            l,
+            dominant.chroma().min(tones.neutral_chroma),
+            dominant.hue(),
+        )
+        .to_color()
+    };
+    let background = neutral(tones.background);
+    let foreground = neutral(tones.foreground);
+    let accent =
+        Oklab::from_lch(tones.accent, accent.chroma().clamp(0.08, 0.2), accent.hue()).to_color();
+
+    let mut colors = [background; 16];
+    colors[7] = neutral(tones.white);
+    colors[8] = neutral(tones.bright_black);
+    colors[15] = foreground;
+
+    for (slot, target) in ANSI_HUES.into_iter().enumerate() {
+        let (hue, chroma) = match nearest_hue(clusters, target) {
+            Some(color) => (color.hue(), color.chroma().clamp(0.1, 0.2)),
+            None => (target, 0.12),
+        };
+        colors[slot + 1] = Oklab::from_lch(tones.normal, chroma, hue).to_color();
+        colors[slot + 9] = Oklab::from_lch(tones.bright, chroma + 0.02, hue).to_color();
+    }
+
+    Scheme {
+        background,
+        foreground,
+        cursor: accent,
+        accent,
+        colors,
+    }
+}
+
+/// The colorful cluster whose hue is closest to `target`, within `HUE_TOLERANCE`
+fn nearest_hue(clusters: &[Cluster], target: f32) -> Option<Oklab> {
+    let hue_distance = |c: &Cluster| {
+        let d = (c.color.hue() - target).rem_euclid(360.0);
+        d.min(360.0 - d)
+    };
+
+    clusters
+        .iter()
+        .filter(|c| c.color.chroma() >= GREY_CHROMA && hue_distance(c) <= HUE_TOLERANCE)
+        .min_by(|x, y| hue_distance(x).total_cmp(&hue_distance(y)))
+        .map(|c| c.color)
+}
+
+/// Group `pixels` into at most `k` clusters with k-means, starting from
+/// k-means++ seeds. Clusters are returned by descending weight.
+pub fn kmeans(pixels: &[Oklab], k: usize) -> Vec<Cluster> {
+    if pixels.is_empty() || k == 0 {
+        return Vec::new();
+    }
+
+    let mut rng = fastrand::Rng::with_seed(SEED);
+    let mut centers = vec![pixels[rng.usize(..pixels.len())]];
+    let mut nearest: Vec<f32> = pixels
+        .iter()
+        .map(|p| p.distance_squared(centers[0]))
+        .collect();
+
+    // k-means++: further pixels are more likely to start a new cluster
+    while centers.len() < k {
+        let total: f32 = nearest.iter().sum();
+        if total <= f32::EPSILON {
+            break;
+        }
+        let mut target = rng.f32() * total;
+        let index = nearest
+            .iter()
+            .position(|d| {
+                target -= d;
+                target <= 0.0
+            })
+            .unwrap_or(pixels.len() - 1);
+        let center = pixels[index];
+        centers.push(center);
+        for (d, p) in nearest.iter_mut().zip(pixels) {
+            *d = d.min(p.distance_squared(center));
+        }
+    }
+
+    let mut assignment = vec![0; pixels.len()];
+    for iteration in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (slot, pixel) in assignment.iter_mut().zip(pixels) {
+            let closest = (0..centers.len())
+                .min_by(|&x, &y| {
+                    pixel
+                        .distance_squared(centers[x])
+                        .total_cmp(&pixel.distance_squared(centers[y]))
+                })
+                .unwrap_or(0);
+            changed |= *slot != closest;
+            *slot = closest;
+        }
+        if !changed && iteration > 0 {
+            break;
+        }
+
+        let mut sums = vec![(Oklab::default(), 0usize); centers.len()];
+        for (&slot, pixel) in assignment.iter().zip(pixels) {
+            let (sum, count) = &mut sums[slot];
+            sum.l += pixel.l;
+            sum.a += pixel.a;
+            sum.b += pixel.b;
+            *count += 1;
+        }
+        for (center, (sum, count)) in centers.iter_mut().zip(sums) {
+            if count > 0 {
+                let n = count as f32;
+                *center = Oklab {
+                    l: sum.l / n,
+                    a: sum.a / n,
+                    b: sum.b / n,
+                };
+            }
+        }
+    }
+
+    let mut counts = vec![0usize; centers.len()];
+    for &slot in &assignment {
+        counts[slot] += 1;
+    }
+    let mut clusters: Vec<Cluster> = centers
+        .into_iter()
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(color, count)| Cluster {
+            color,
+            weight: count as f32 / pixels.len() as f32,
+        })
+        .collect();
+    clusters.sort_by(|x, y| y.weight.total_cmp(&x.weight));
+    clusters
+}
+
+/// Decode the image and reduce it to its main colors
+pub fn extract_clusters(path: &Path) -> Result<Vec<Cluster>> {
+    let reader = ImageReader::open(path)
+        .with_context(|| format!("Failed to open image: {}", path.display()))?
+        .with_guessed_format()
+        .with_context(|| format!("Failed to read image: {}", path.display()))?;
+    // Accepted as a wallpaper, but decoding AVIF would need the dav1d C library
+    if reader.format() == Some(ImageFormat::Avif) {
+        return Err(anyhow!(
+            "Unsupported format: the native generator cannot read AVIF images ({}). Use another generator or convert the image.",
+            path.display()
+        ));
+    }
+    let image = reader
+        .decode()
+        .with_context(|| format!("Failed to decode image: {}", path.display()))?;
+
+    let sample = image
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    // Fully transparent areas are not part of what is seen
+    let pixels: Vec<Oklab> = sample
+        .pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| Color::new(p[0], p[1], p[2]).to_oklab())
+        .collect();
+    if pixels.is_empty() {
+        return Err(anyhow!("Image has no visible pixels: {}", path.display()));
+    }
+
+    Ok(kmeans(&pixels, CLUSTERS))
+}
+
+pub fn get_cache_directory() -> Result<PathBuf> {
+    Ok(get_cache_home()?.join("wallpaper"))
+}
+
+/// Cache directory shared by all programs; pywal writes to `wal/` in it
+pub fn get_cache_home() -> Result<PathBuf> {
+    // Try to use XDG_CACHE_HOME first, fallback to ~/.cache
+    if let Ok(xdg_cache_home) = std::env::var("XDG_CACHE_HOME") {
+        Ok(PathBuf::from(xdg_cache_home))
+    } else if let Ok(home) = std::env::var("HOME") {
+        Ok(PathBuf::from(home).join(".cache"))
+    } else {
+        Err(anyhow!(
+            "Unable to determine cache directory. HOME environment variable not set."
+        ))
+    }
+}
+
+/// Write palette.json and `colors` (the 16 active colors, one per line)
+fn save(palette: &Palette, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    let json = serde_json::to_string_pretty(palette).context("Failed to serialize palette")?;
+    write_atomic(&dir.join(PALETTE_FILENAME), json.as_bytes())?;
+
+    let colors: String = palette
+        .active()
+        .colors
+        .iter()
+        .map(|color| format!("{}\n", color))
+        .collect();
+    write_atomic(&dir.join(COLORS_FILENAME), colors.as_bytes())
+}
+
+/// The `native` generator: extract the palette of `path` and save it to the cache directory
+pub fn generate(path: &Path, is_light: bool) -> Result<()> {
+    if let Some(signal) = signals::received() {
+        return Err(anyhow!(
+            "Not extracting the palette: interrupted by signal {}",
+            signal
+        ));
+    }
+
+    let started = Instant::now();
+    let result = extract_clusters(path).and_then(|clusters| {
+        let palette = Palette::from_clusters(path, &clusters, is_light);
+        let dir = get_cache_directory()?;
+        save(&palette, &dir)?;
+        Ok((palette, dir))
+    });
+    let elapsed = humantime::format_duration(logging::round_to_millis(started.elapsed()));
+
+    match &result {
+        Ok((palette, dir)) => {
+            let scheme = palette.active();
+            let colors: Vec<String> = scheme.colors.map(Color::hex).to_vec();
+            info!("[native] {}", colors[..8].join(" "));
+            info!("[native] {}", colors[8..].join(" "));
+            info!("[native] Palette written to {}", dir.display());
+            verbose!("`native` finished in {}", elapsed);
+            logging::record(&format!(
+                "native palette from {}: background {}, foreground {}, accent {} in {}",
+                path.display(),
+                scheme.background,
+                scheme.foreground,
+                scheme.accent,
+                elapsed
+            ));
+        }
+        Err(e) => logging::record(&format!(
+            "native palette from {}: {:#} after {}",
+            path.display(),
+            e,
+            elapsed
+        )),
+    }
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn cluster(hex: &str, weight: f32) -> Cluster {
+        Cluster {
+            color: Color::from_hex(hex).unwrap().to_oklab(),
+            weight,
+        }
+    }
+
+    /// WCAG contrast ratio
+    fn contrast(x: Color, y: Color) -> f32 {
+        let luminance = |c: Color| {
+            let [r, g, b] = [c.r, c.g, c.b].map(|v| srgb_to_linear(v as f32 / 255.0));
+            0.2126 * r + 0.7152 * g + 0.0722 * b
+        };
+        let (x, y) = (luminance(x), luminance(y));
+        (x.max(y) + 0.05) / (x.min(y) + 0.05)
+    }
+
+    #[test]
+    fn test_hex() {
+        let color = Color::from_hex("#1a2B3c").unwrap();
+        assert_eq!(color, Color::new(0x1a, 0x2b, 0x3c));
+        assert_eq!(color.hex(), "#1a2b3c");
+        assert_eq!(Color::from_hex("ff0000"), Some(Color::new(255, 0, 0)));
+
+        assert_eq!(Color::from_hex("#fff"), None);
+        assert_eq!(Color::from_hex("#gg0000"), None);
+        assert_eq!(Color::from_hex("#ä0000"), None);
+    }
+
+    #[test]
+    fn test_oklab_round_trip() {
+        for hex in [
+            "#000000", "#ffffff", "#ff0000", "#00ff00", "#0000ff", "#7f3fbf",
+        ] {
+            let color = Color::from_hex(hex).unwrap();
+            assert_eq!(color.to_oklab().to_color(), color, "{}", hex);
+        }
+
+        let white = Color::new(255, 255, 255).to_oklab();
+        assert!((white.l - 1.0).abs() < 0.001);
+        assert!(white.chroma() < 0.001);
+    }
+
+    #[test]
+    fn test_out_of_gamut_keeps_lightness_and_hue() {
+        let requested = Oklab::from_lch(0.7, 0.4, 145.0);
+        let fitted = requested.to_color().to_oklab();
+        assert!((fitted.l - 0.7).abs() < 0.01);
+        assert!((fitted.hue() - 145.0).abs() < 2.0);
+        assert!(fitted.chroma() < 0.4);
+    }
+
+    #[test]
+    fn test_kmeans_finds_colors() {
+        let red = Color::new(200, 30, 30).to_oklab();
+        let blue = Color::new(30, 30, 200).to_oklab();
+        let mut pixels = vec![red; 300];
+        pixels.extend(vec![blue; 100]);
+
+        let clusters = kmeans(&pixels, 4);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].color.to_color(), Color::new(200, 30, 30));
+        assert!((clusters[0].weight - 0.75).abs() < 0.001);
+        assert_eq!(clusters[1].color.to_color(), Color::new(30, 30, 200));
+
+        assert!(kmeans(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_kmeans_is_deterministic() {
+        let pixels: Vec<Oklab> = (0..=255u8)
+            .map(|v| Color::new(v, 255 - v, v / 2).to_oklab())
+            .collect();
+        assert_eq!(kmeans(&pixels, 8), kmeans(&pixels, 8));
+    }
+
+    #[test]
+    fn test_dark_and_light_schemes() {
+        let clusters = [
+            cluster("#1d3b2a", 0.6),
+            cluster("#c83232", 0.1),
+            cluster("#3a6fd8", 0.3),
+        ];
+
+        let dark = derive_scheme(&clusters, Mode::Dark);
+        let light = derive_scheme(&clusters, Mode::Light);
+
+        assert!(dark.background.to_oklab().l < 0.3);
+        assert!(light.background.to_oklab().l > 0.9);
+        assert_eq!(dark.colors[0], dark.background);
+        assert_eq!(dark.colors[15], dark.foreground);
+        assert_ne!(dark, light);
+
+        for scheme in [&dark, &light] {
+            assert!(contrast(scheme.background, scheme.foreground) > 7.0);
+            for color in &scheme.colors[1..7] {
+                assert!(contrast(scheme.background, *color) > 3.0, "{}", color);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ansi_colors_follow_image() {
+        let clusters = [cluster("#202020", 0.8), cluster("#3a6fd8", 0.2)];
+        let scheme = derive_scheme(&clusters, Mode::Dark);
+
+        // Blue takes the image's blue; red has no nearby image color and keeps its hue
+        let blue = scheme.colors[4].to_oklab().hue();
+        assert!((blue - clusters[1].color.hue()).abs() < 2.0);
+        let red = scheme.colors[1].to_oklab().hue();
+        assert!((red - ANSI_HUES[0]).abs() < 2.0);
+
+        // The accent is the most colorful image color
+        assert!((scheme.accent.to_oklab().hue() - blue).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_background_follows_largest_area() {
+        // Three shades of blue cover more than the single orange cluster
+        let clusters = [
+            cluster("#d07828", 0.4),
+            cluster("#1e3a5a", 0.2),
+            cluster("#1e425a", 0.2),
+            cluster("#1e4a5a", 0.2),
+        ];
+        let scheme = derive_scheme(&clusters, Mode::Dark);
+        let hue = scheme.background.to_oklab().hue();
+        assert!((hue - clusters[2].color.hue()).abs() < 20.0, "{}", hue);
+    }
+
+    #[test]
+    fn test_grey_image() {
+        let clusters = [cluster("#808080", 1.0)];
+        let scheme = derive_scheme(&clusters, Mode::Dark);
+        assert!(scheme.background.to_oklab().chroma() < 0.01);
+        for (index, target) in ANSI_HUES.into_iter().enumerate() {
+            let hue = scheme.colors[index + 1].to_oklab().hue();
+            assert!((hue - target).abs() < 2.0, "color{}", index + 1);
+        }
+    }
+
+    #[test]
+    fn test_extract_and_save() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("wall.png");
+        let image = image::RgbImage::from_fn(64, 32, |x, _| {
+            if x < 48 {
+                image::Rgb([20, 60, 40])
+            } else {
+                image::Rgb([220, 180, 40])
+            }
+        });
+        image.save(&path).unwrap();
+
+        let clusters = extract_clusters(&path).unwrap();
+        assert!(clusters[0].weight > 0.6);
+        assert!(
+            clusters[0]
+                .color
+                .distance_squared(Color::new(20, 60, 40).to_oklab())
+                < 0.001
+        );
+
+        let palette = Palette::from_clusters(&path, &clusters, true);
+        assert_eq!(palette.mode, Mode::Light);
+        assert_eq!(palette.active(), &palette.light);
+
+        let out = dir.path().join("cache");
+        save(&palette, &out).unwrap();
+
+        let json = fs::read_to_string(out.join(PALETTE_FILENAME)).unwrap();
+        assert!(json.contains("\"mode\": \"light\""));
+        let loaded: Palette = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, palette);
+
+        let colors = fs::read_to_string(out.join(COLORS_FILENAME)).unwrap();
+        assert_eq!(colors.lines().count(), 16);
+        assert_eq!(
+            colors.lines().next(),
+            Some(palette.light.colors[0].hex().as_str())
+        );
+    }
+
+    #[test]
+    fn test_lighten_and_darken() {
+        let color = Color::from_hex("#3a6fd8").unwrap();
+        let lighter = color.lighten(10.0).to_oklab();
+        assert!((lighter.l - color.to_oklab().l - 0.1).abs() < 0.01);
+        assert!((lighter.hue() - color.to_oklab().hue()).abs() < 2.0);
+
+        assert_eq!(color.darken(100.0), Color::new(0, 0, 0));
+        assert_eq!(color.lighten(100.0), Color::new(255, 255, 255));
+        assert_eq!(color.lighten(0.0), color);
+    }
+
+    #[test]
+    fn test_extract_rejects_non_images() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notes.png");
+        fs::write(&path, "not an image").unwrap();
+        assert!(extract_clusters(&path).is_err());
+    }
+
+    #[test]
+    fn test_extract_rejects_avif() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("forest.avif");
+        let mut avif = vec![0, 0, 0, 0x1c];
+        avif.extend_from_slice(b"ftypavif\0\0\0\0avifmif1miaf");
+        fs::write(&path, avif).unwrap();
+
+        let error = format!("{:#}", extract_clusters(&path).unwrap_err());
+        assert!(error.starts_with("Unsupported format"), "{}", error);
+    }
+}
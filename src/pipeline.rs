@@ -0,0 +1,1437 @@
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    borrow::{Borrow, Cow},
+    ffi::OsString,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crate::backends::Backend;
+use crate::config::{Config, StepConfig, TemplateConfig};
+use crate::generators::Generator;
+use crate::logging::{self, debug, info, warning};
+use crate::programs::Program;
+use crate::signals;
+
+/// Built-in step that renders `[[templates]]`
+const TEMPLATES_STEP: &str = "templates";
+/// Argument placeholder for the outputs the wallpaper is set on
+const OUTPUTS_PLACEHOLDER: &str = "{outputs}";
+
+/// Values available to pipeline steps when rendering their arguments
+#[derive(Debug, Clone, Copy)]
+pub struct StepContext<'a> {
+    pub path: &'a Path,
+    pub matugen_type: &'a str,
+    pub is_light: bool,
+    pub backend: Backend,
+    pub generators: &'a [Generator],
+    /// Outputs the wallpaper is set on. Empty means every output.
+    pub outputs: &'a [String],
+    /// `[[templates]]` rendered by the `templates` step
+    pub templates: &'a [TemplateConfig],
+    /// Generator whose colors fill the templates. None means the first
+    /// generator in the pipeline.
+    pub template_colors: Option<Generator>,
+}
+
+impl<'a> StepContext<'a> {
+    pub fn new(
+        path: &'a Path,
+        matugen_type: &'a str,
+        is_light: bool,
+        backend: Backend,
+        generators: &'a [Generator],
+    ) -> Self {
+        Self {
+            path,
+            matugen_type,
+            is_light,
+            backend,
+            generators,
+            outputs: &[],
+            templates: &[],
+            template_colors: None,
+        }
+    }
+
+    pub fn with_outputs(self, outputs: &'a [String]) -> Self {
+        Self { outputs, ..self }
+    }
+
+    pub fn with_templates(
+        self,
+        templates: &'a [TemplateConfig],
+        template_colors: Option<Generator>,
+    ) -> Self {
+        Self {
+            templates,
+            template_colors,
+            ..self
+        }
+    }
+
+    fn backend_steps(&self) -> Vec<Step> {
+        let programs = self.backend.programs(self.path, self.outputs);
+        programs.into_iter().map(Step::sequential).collect()
+    }
+
+    fn generator_steps(&self) -> Vec<Step> {
+        self.generators
+            .iter()
+            .map(|g| Step::parallel(g.program(self)))
+            .collect()
+    }
+
+    /// Nothing to do without configured templates
+    fn template_steps(&self) -> Vec<Step> {
+        if self.templates.is_empty() {
+            return Vec::new();
+        }
+        vec![Step::sequential(Program::templates(
+            self.path,
+            self.is_light,
+            self.templates,
+            self.template_colors,
+        ))]
+    }
+}
+
+/// What a failing step means for the rest of the run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StepPolicy {
+    /// A failure stops the run and rolls back
+    #[default]
+    Required,
+    /// A failure is reported and the run goes on
+    Optional,
+    /// Left out when the program is not installed, required otherwise
+    SkipIfMissing,
+}
+
+impl StepPolicy {
+    /// Name as written in config.toml
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StepPolicy::Required => "required",
+            StepPolicy::Optional => "optional",
+            StepPolicy::SkipIfMissing => "skip-if-missing",
+        }
+    }
+}
+
+/// A pipeline step. Consecutive parallel steps run at the same time.
+#[derive(Debug)]
+pub struct Step {
+    pub program: Program,
+    pub parallel: bool,
+    pub policy: StepPolicy,
+    /// The program and everything it started are stopped after this long
+    pub timeout: Duration,
+}
+
+impl Step {
+    pub fn sequential(program: Program) -> Self {
+        Self {
+            timeout: program.default_timeout(),
+            program,
+            parallel: false,
+            policy: StepPolicy::Required,
+        }
+    }
+
+    /// Daemon programs are never run in parallel since they do not exit
+    pub fn parallel(program: Program) -> Self {
+        Self {
+            timeout: program.default_timeout(),
+            parallel: !program.is_daemon(),
+            program,
+            policy: StepPolicy::Required,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.program.get_step_name()
+    }
+}
+
+/// Built-in pipeline used when the config file has no `[[pipeline]]` entries:
+/// the wallpaper is set first, then palette generators run in parallel, then
+/// templates are rendered
+pub fn default_pipeline(ctx: &StepContext) -> Vec<Step> {
+    let mut steps = ctx.backend_steps();
+    steps.extend(ctx.generator_steps());
+    steps.extend(ctx.template_steps());
+    with_template_source(steps)
+}
+
+pub fn build_pipeline(config: &Config, ctx: &StepContext) -> Result<Vec<Step>> {
+    if config.pipeline.is_empty() {
+        return Ok(with_timeout(default_pipeline(ctx), config.step_timeout));
+    }
+
+    let mut steps = Vec::new();
+    for step in config.pipeline.iter().filter(|step| step.enabled) {
+        let built = build_step(step, ctx)?;
+        steps.extend(with_timeout(built, step.timeout.or(config.step_timeout)));
+    }
+
+    // Templates go last unless the pipeline places (or disables) them itself
+    let lists_templates = config
+        .pipeline
+        .iter()
+        .any(|step| step.name == TEMPLATES_STEP && !step.is_custom());
+    if !lists_templates {
+        steps.extend(ctx.template_steps());
+    }
+    Ok(with_template_source(steps))
+}
+
+/// Fill templates from the first generator in the pipeline unless
+/// `template_colors` names one, and have that generator keep its colors for them
+fn with_template_source(mut steps: Vec<Step>) -> Vec<Step> {
+    let first = steps.iter().find_map(|step| step.program.generator());
+    let mut sources = Vec::new();
+    for step in &mut steps {
+        if let Program::Templates { source, .. } = &mut step.program {
+            if source.is_none() {
+                *source = first;
+            }
+            sources.extend(*source);
+        }
+    }
+
+    steps
+        .into_iter()
+        .map(|mut step| {
+            if step
+                .program
+                .generator()
+                .is_some_and(|generator| sources.contains(&generator))
+            {
+                step.program = step.program.template_source();
+            }
+            step
+        })
+        .collect()
+}
+
+/// Replace the programs' default timeouts when one is configured
+fn with_timeout(mut steps: Vec<Step>, timeout: Option<Duration>) -> Vec<Step> {
+    if let Some(timeout) = timeout {
+        for step in &mut steps {
+            step.timeout = timeout;
+        }
+    }
+    steps
+}
+
+fn build_step(step: &StepConfig, ctx: &StepContext) -> Result<Vec<Step>> {
+    if !step.is_custom() {
+        let mut steps = builtin_step(&step.name, ctx)?;
+        for built in &mut steps {
+            if let Some(parallel) = step.parallel {
+                built.parallel = parallel && !built.program.is_daemon();
+            }
+            built.policy = step.policy;
+        }
+        return Ok(steps);
+    }
+
+    let program = step.program.as_deref().unwrap_or(&step.name);
+
+    let mut args: Vec<OsString> = step
+        .args
+        .iter()
+        .flatten()
+        .map(|template| render_arg(template, ctx))
+        .collect();
+
+    if ctx.is_light {
+        args.extend(step.light_args.iter().map(|t| render_arg(t, ctx)));
+    }
+
+    let program = Program::custom(&step.name, program, args);
+    Ok(vec![Step {
+        timeout: program.default_timeout(),
+        program,
+        parallel: step.parallel.unwrap_or(false),
+        policy: step.policy,
+    }])
+}
+
+fn builtin_step(name: &str, ctx: &StepContext) -> Result<Vec<Step>> {
+    match name {
+        "backend" => Ok(ctx.backend_steps()),
+        "swww" => Ok(vec![Step::sequential(Program::swww_outputs(
+            ctx.path,
+            ctx.outputs,
+        ))]),
+        "generators" => Ok(ctx.generator_steps()),
+        TEMPLATES_STEP => Ok(ctx.template_steps()),
+        _ => match Generator::from_str(name, false) {
+            Ok(generator) => Ok(vec![Step::parallel(generator.program(ctx))]),
+            Err(_) => Err(anyhow!(
+                "Unknown built-in step `{}`. Set `program` and `args` for custom steps.",
+                name
+            )),
+        },
+    }
+}
+
+/// Custom steps cannot be restricted to some outputs unless they take `{outputs}`,
+/// so they would change every output
+pub fn check_custom_steps_take_outputs(config: &Config) -> Result<()> {
+    let unaware: Vec<String> = config
+        .pipeline
+        .iter()
+        .filter(|step| step.enabled && step.is_custom())
+        .filter(|step| {
+            !step
+                .args
+                .iter()
+                .flatten()
+                .chain(&step.light_args)
+                .any(|arg| arg.contains(OUTPUTS_PLACEHOLDER))
+        })
+        .map(|step| format!("`{}`", step.name))
+        .collect();
+
+    if unaware.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "With --output every custom step needs {} in its args, otherwise it changes every output. Missing in: {}",
+        OUTPUTS_PLACEHOLDER,
+        unaware.join(", ")
+    ))
+}
+
+fn render_arg(template: &str, ctx: &StepContext) -> OsString {
+    // Keep non UTF-8 paths intact when the argument is just the path
+    if template == "{path}" {
+        return ctx.path.as_os_str().to_os_string();
+    }
+
+    let light = if ctx.is_light { "light" } else { "dark" };
+    let placeholders = [
+        ("{path}", ctx.path.to_string_lossy()),
+        ("{type}", Cow::from(ctx.matugen_type)),
+        ("{light}", Cow::from(light)),
+        (OUTPUTS_PLACEHOLDER, Cow::from(ctx.outputs.join(","))),
+    ];
+
+    // One pass, so a value that happens to contain a placeholder stays as it is
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match placeholders.iter().find(|(name, _)| rest.starts_with(name)) {
+            Some((name, value)) => {
+                rendered.push_str(value);
+                rest = &rest[name.len()..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    OsString::from(rendered)
+}
+
+#[allow(dead_code)]
+pub fn run_pipeline(steps: &[Step], dry_run: bool) -> Result<()> {
+    let steps: Vec<&Step> = steps.iter().collect();
+    run_steps(&steps, dry_run, &mut PipelineReport::default()).map_err(|failure| failure.error)
+}
+
+/// How a step ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    Succeeded,
+    Failed(String),
+    Skipped(String),
+}
+
+/// Outcome of every step of a run that went through
+#[derive(Debug, Default)]
+pub struct PipelineReport {
+    pub steps: Vec<(String, StepStatus)>,
+}
+
+impl PipelineReport {
+    fn push(&mut self, step: &Step, status: StepStatus) {
+        self.steps.push((step.name().to_string(), status));
+    }
+
+    fn count(&self, matches: fn(&StepStatus) -> bool) -> usize {
+        self.steps
+            .iter()
+            .filter(|(_, status)| matches(status))
+            .count()
+    }
+
+    /// Whether an optional step failed or a step was skipped
+    pub fn is_partial(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|(_, status)| *status != StepStatus::Succeeded)
+    }
+
+    pub fn print_summary(&self) {
+        info!(
+            "Summary: {} succeeded, {} failed, {} skipped",
+            self.count(|s| *s == StepStatus::Succeeded),
+            self.count(|s| matches!(s, StepStatus::Failed(_))),
+            self.count(|s| matches!(s, StepStatus::Skipped(_)))
+        );
+        for (name, status) in &self.steps {
+            match status {
+                StepStatus::Succeeded => info!("  ok       {}", name),
+                // The program's output was already shown with the warning
+                StepStatus::Failed(reason) => info!(
+                    "  failed   {}: {}",
+                    name,
+                    reason.lines().next().unwrap_or_default()
+                ),
+                StepStatus::Skipped(reason) => info!("  skipped  {}: {}", name, reason),
+            }
+        }
+    }
+}
+
+/// Where a pipeline stopped
+struct StepFailure {
+    /// Name of the failed step, or the failed steps of a parallel group
+    step: String,
+    /// Whether an earlier step (or another step of the same group) had already succeeded
+    changed: bool,
+    error: anyhow::Error,
+}
+
+fn run_steps(
+    steps: &[&Step],
+    dry_run: bool,
+    report: &mut PipelineReport,
+) -> std::result::Result<(), StepFailure> {
+    for (index, group) in group_steps(steps).into_iter().enumerate() {
+        if group.len() == 1 || dry_run {
+            for (offset, step) in group.iter().enumerate() {
+                match step.program.execute_with_dry_run(dry_run, step.timeout) {
+                    Ok(()) => report.push(step, StepStatus::Succeeded),
+                    Err(e) if step.policy == StepPolicy::Optional => {
+                        warning!("optional step `{}` failed: {:#}", step.name(), e);
+                        report.push(step, StepStatus::Failed(format!("{:#}", e)));
+                    }
+                    Err(e) => {
+                        return Err(StepFailure {
+                            step: step.name().to_string(),
+                            changed: index > 0 || offset > 0,
+                            error: e.context(format!("Pipeline step `{}` failed", step.name())),
+                        });
+                    }
+                }
+            }
+        } else {
+            run_parallel(group, report).map_err(|failure| StepFailure {
+                changed: failure.changed || index > 0,
+                ..failure
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// What to run when the pipeline fails halfway: the previous wallpaper and palette
+#[derive(Debug)]
+pub struct Rollback {
+    pub path: PathBuf,
+    pub steps: Vec<Step>,
+}
+
+/// Run `steps` as one unit. Nothing runs unless every required program is installed.
+/// If a required step fails after an earlier one already changed something,
+/// `rollback` is run so the screen and palette do not end up half-updated.
+/// Failed optional steps and skipped steps are listed in a summary.
+pub fn run_transaction(
+    steps: &[Step],
+    rollback: Option<Rollback>,
+    dry_run: bool,
+) -> Result<PipelineReport> {
+    for step in steps {
+        debug!(
+            "Step `{}`: {} ({}, timeout {}{})",
+            step.name(),
+            step.program.command_line(),
+            step.policy.as_str(),
+            humantime::format_duration(step.timeout),
+            if step.parallel { ", parallel" } else { "" }
+        );
+    }
+
+    let left_out = check_installed(steps, dry_run)?;
+    let runnable: Vec<&Step> = steps
+        .iter()
+        .zip(&left_out)
+        .filter(|(_, status)| status.is_none())
+        .map(|(step, _)| step)
+        .collect();
+
+    let mut ran = PipelineReport::default();
+    let failure = match run_steps(&runnable, dry_run, &mut ran) {
+        Ok(()) => {
+            // Report every step in pipeline order
+            let mut ran = ran.steps.into_iter();
+            let report = PipelineReport {
+                steps: steps
+                    .iter()
+                    .zip(left_out)
+                    .filter_map(|(step, status)| match status {
+                        Some(status) => Some((step.name().to_string(), status)),
+                        None => ran.next(),
+                    })
+                    .collect(),
+            };
+            if report.is_partial() {
+                report.print_summary();
+            }
+            return Ok(report);
+        }
+        Err(failure) => failure,
+    };
+
+    let outcome = if let Some(signal) = signals::received() {
+        format!(
+            "Interrupted by signal {}, so nothing was rolled back.",
+            signal
+        )
+    } else if !failure.changed {
+        "No earlier step had run, so nothing was rolled back.".to_string()
+    } else if let Some(rollback) = rollback {
+        info!(
+            "Step `{}` failed, rolling back to {}",
+            failure.step,
+            rollback.path.display()
+        );
+        let steps: Vec<&Step> = rollback.steps.iter().collect();
+        match run_steps(&steps, dry_run, &mut PipelineReport::default()) {
+            Ok(()) => format!(
+                "Rolled back to the previous wallpaper and palette: {}",
+                rollback.path.display()
+            ),
+            Err(e) => format!(
+                "Rolling back to {} failed as well at step `{}`: {:#}. Wallpaper and palette may not match.",
+                rollback.path.display(),
+                e.step,
+                e.error
+            ),
+        }
+    } else {
+        "There is no previous wallpaper in history to roll back to; wallpaper and palette may not match."
+            .to_string()
+    };
+
+    Err(failure
+        .error
+        .context(format!("Step `{}` failed. {}", failure.step, outcome)))
+}
+
+/// For each step, why it is left out (`None` when it runs). Missing programs of
+/// required steps fail the run before anything starts. A dry run keeps required
+/// steps, so it shows what would run.
+fn check_installed(steps: &[Step], dry_run: bool) -> Result<Vec<Option<StepStatus>>> {
+    let mut left_out = Vec::with_capacity(steps.len());
+    let mut missing = Vec::new();
+
+    for step in steps {
+        if step.program.is_installed() {
+            left_out.push(None);
+            continue;
+        }
+
+        let reason = format!("`{}` is not installed", step.program.get_program_name());
+        left_out.push(match step.policy {
+            StepPolicy::Required => {
+                if !dry_run {
+                    missing.push(format!(
+                        "`{}` ({})",
+                        step.program.get_program_name(),
+                        step.name()
+                    ));
+                }
+                None
+            }
+            StepPolicy::Optional => {
+                warning!("optional step `{}` failed: {}", step.name(), reason);
+                Some(StepStatus::Failed(reason))
+            }
+            StepPolicy::SkipIfMissing => {
+                info!("Skipping step `{}`: {}", step.name(), reason);
+                logging::record(&format!("skipped step `{}`: {}", step.name(), reason));
+                Some(StepStatus::Skipped(reason))
+            }
+        });
+    }
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Not installed or not in PATH: {}. Nothing was changed.",
+            missing.join(", ")
+        ));
+    }
+    Ok(left_out)
+}
+
+/// Split steps into runs of consecutive parallel steps; sequential steps stand alone
+fn group_steps<S: Borrow<Step>>(steps: &[S]) -> Vec<&[S]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+
+    for (index, step) in steps.iter().enumerate() {
+        let joins_group =
+            step.borrow().parallel && index > start && steps[index - 1].borrow().parallel;
+        if !joins_group && index > start {
+            groups.push(&steps[start..index]);
+            start = index;
+        }
+    }
+    if start < steps.len() {
+        groups.push(&steps[start..]);
+    }
+
+    groups
+}
+
+/// Run every step of the group at once. Output is streamed line by line with
+/// each step's name as prefix; failures of required steps are reported together
+/// once all of them have finished.
+fn run_parallel(
+    group: &[&Step],
+    report: &mut PipelineReport,
+) -> std::result::Result<(), StepFailure> {
+    for step in group {
+        info!("Running: {}", step.program.command_line());
+    }
+
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = group
+            .iter()
+            .map(|step| scope.spawn(|| step.program.execute_checked(step.timeout)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Step thread panicked")))
+            })
+            .collect()
+    });
+
+    let mut failed = Vec::new();
+    let mut failures = Vec::new();
+
+    for (step, result) in group.iter().zip(results) {
+        match result {
+            Ok(()) => report.push(step, StepStatus::Succeeded),
+            Err(e) if step.policy == StepPolicy::Optional => {
+                warning!("optional step `{}` failed: {:#}", step.name(), e);
+                report.push(step, StepStatus::Failed(format!("{:#}", e)));
+            }
+            Err(e) => {
+                failed.push(step.name());
+                failures.push(format!("{}: {:#}", step.name(), e));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(StepFailure {
+            step: failed.join("`, `"),
+            changed: failed.len() < group.len(),
+            error: anyhow!(
+                "{} of {} parallel steps failed:\n  {}",
+                failures.len(),
+                group.len(),
+                failures.join("\n  ")
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Build the pipeline from config and run every enabled step. On failure the
+/// pipeline is run again for `previous`, the wallpaper that was on screen before.
+pub fn apply_wallpaper(
+    config: &Config,
+    ctx: &StepContext,
+    previous: Option<&StepContext>,
+    dry_run: bool,
+) -> Result<PipelineReport> {
+    let steps = build_pipeline(config, ctx)?;
+    let rollback = match previous {
+        Some(previous) => Some(Rollback {
+            path: previous.path.to_path_buf(),
+            steps: build_pipeline(config, previous)?,
+        }),
+        None => None,
+    };
+    run_transaction(&steps, rollback, dry_run)
+}
+
+/// Only set the wallpaper, without generating a palette. Used for secondary outputs.
+pub fn apply_backend_only(
+    config: &Config,
+    ctx: &StepContext,
+    previous: Option<&StepContext>,
+    dry_run: bool,
+) -> Result<PipelineReport> {
+    let rollback = previous.map(|previous| Rollback {
+        path: previous.path.to_path_buf(),
+        steps: with_timeout(previous.backend_steps(), config.step_timeout),
+    });
+    let steps = with_timeout(ctx.backend_steps(), config.step_timeout);
+    run_transaction(&steps, rollback, dry_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::DEFAULT_GENERATORS;
+    use std::fs;
+
+    fn ctx(is_light: bool) -> StepContext<'static> {
+        StepContext::new(
+            Path::new("/test/image.jpg"),
+            "scheme-content",
+            is_light,
+            Backend::Swww,
+            DEFAULT_GENERATORS,
+        )
+    }
+
+    #[test]
+    fn test_default_pipeline_without_config() {
+        let steps = build_pipeline(&Config::default(), &ctx(false)).unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s.name()).collect();
+
+        assert_eq!(names, vec!["swww", "matugen", "wallust"]);
+    }
+
+    #[test]
+    fn test_default_pipeline_follows_backend() {
+        let ctx = StepContext {
+            backend: Backend::Feh,
+            ..ctx(false)
+        };
+        let steps = build_pipeline(&Config::default(), &ctx).unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s.name()).collect();
+
+        assert_eq!(names, vec!["feh", "matugen", "wallust"]);
+    }
+
+    #[test]
+    fn test_default_pipeline_follows_generators() {
+        let generators = [Generator::Pywal, Generator::Hellwal];
+        let ctx = StepContext {
+            generators: &generators,
+            ..ctx(true)
+        };
+        let steps = build_pipeline(&Config::default(), &ctx).unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s.name()).collect();
+
+        assert_eq!(names, vec!["swww", "pywal", "hellwal"]);
+    }
+
+    #[test]
+    fn test_generators_step_in_config() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "generators"
+
+            [[pipeline]]
+            name = "pywal16"
+            "#,
+        )
+        .unwrap();
+
+        let steps = build_pipeline(&config, &ctx(false)).unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s.name()).collect();
+
+        assert_eq!(names, vec!["matugen", "wallust", "pywal16"]);
+    }
+
+    #[test]
+    fn test_templates_step() {
+        let with_templates = |pipeline: &str| {
+            let config = Config::from_toml_str(&format!(
+                "{}\n[[templates]]\ninput = \"a.tpl\"\noutput = \"a.conf\"",
+                pipeline
+            ))
+            .unwrap();
+            let ctx = ctx(false).with_templates(&config.templates, None);
+            let steps = build_pipeline(&config, &ctx).unwrap();
+            steps
+                .iter()
+                .map(|s| s.name().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // No templates, no step
+        let steps = build_pipeline(&Config::default(), &ctx(false)).unwrap();
+        assert!(steps.iter().all(|s| s.name() != "templates"));
+
+        assert_eq!(
+            with_templates(""),
+            vec!["swww", "matugen", "wallust", "templates"]
+        );
+        assert_eq!(
+            with_templates("[[pipeline]]\nname = \"matugen\""),
+            vec!["matugen", "templates"]
+        );
+        assert_eq!(
+            with_templates("[[pipeline]]\nname = \"templates\"\n[[pipeline]]\nname = \"matugen\""),
+            vec!["templates", "matugen"]
+        );
+        assert_eq!(
+            with_templates(
+                "[[pipeline]]\nname = \"matugen\"\n[[pipeline]]\nname = \"templates\"\nenabled = false"
+            ),
+            vec!["matugen"]
+        );
+    }
+
+    #[test]
+    fn test_templates_source() {
+        let source = |pipeline: &str, template_colors: Option<Generator>| {
+            let config = Config::from_toml_str(&format!(
+                "{}\n[[templates]]\ninput = \"a.tpl\"\noutput = \"a.conf\"",
+                pipeline
+            ))
+            .unwrap();
+            let ctx = ctx(false).with_templates(&config.templates, template_colors);
+            let steps = build_pipeline(&config, &ctx).unwrap();
+            match &steps.last().unwrap().program {
+                Program::Templates { source, .. } => *source,
+                _ => panic!("Expected Templates variant"),
+            }
+        };
+
+        // First generator of the pipeline
+        assert_eq!(source("", None), Some(Generator::Matugen));
+        assert_eq!(
+            source(
+                "[[pipeline]]\nname = \"swww\"\n[[pipeline]]\nname = \"hellwal\"",
+                None
+            ),
+            Some(Generator::Hellwal)
+        );
+        assert_eq!(source("[[pipeline]]\nname = \"swww\"", None), None);
+
+        // `template_colors` wins
+        assert_eq!(source("", Some(Generator::Native)), Some(Generator::Native));
+    }
+
+    #[test]
+    fn test_only_template_source_keeps_colors() {
+        let command_lines = |templates: &str, template_colors: Option<Generator>| {
+            let config = Config::from_toml_str(templates).unwrap();
+            let ctx = ctx(false).with_templates(&config.templates, template_colors);
+            let steps = build_pipeline(&config, &ctx).unwrap();
+            steps
+                .iter()
+                .map(|s| s.program.command_line())
+                .collect::<Vec<_>>()
+        };
+        let templates = "[[templates]]\ninput = \"a.tpl\"\noutput = \"a.conf\"";
+
+        // Without templates the generators run as they always did
+        let lines = command_lines("", None);
+        assert_eq!(
+            lines[1],
+            "matugen image /test/image.jpg --type scheme-content"
+        );
+
+        let lines = command_lines(templates, None);
+        assert_eq!(
+            lines[1],
+            "matugen image /test/image.jpg --type scheme-content --json hex"
+        );
+        assert_eq!(lines[2], "wallust run /test/image.jpg -k");
+
+        let lines = command_lines(templates, Some(Generator::Wallust));
+        assert_eq!(
+            lines[1],
+            "matugen image /test/image.jpg --type scheme-content"
+        );
+
+        let config = Config::from_toml_str(templates).unwrap();
+        let ctx = ctx(false).with_templates(&config.templates, Some(Generator::Wallust));
+        let steps = build_pipeline(&config, &ctx).unwrap();
+        assert!(matches!(steps[2].program, Program::TemplateSource(_)));
+        assert_eq!(steps[2].name(), "wallust");
+    }
+
+    #[test]
+    fn test_outputs_passed_to_backend() {
+        let outputs = vec!["DP-1".to_string()];
+        let ctx = ctx(false).with_outputs(&outputs);
+        let steps = build_pipeline(&Config::default(), &ctx).unwrap();
+
+        match &steps[0].program {
+            Program::Swww { outputs, .. } => assert_eq!(outputs, &vec!["DP-1"]),
+            _ => panic!("Expected Swww variant"),
+        }
+    }
+
+    #[test]
+    fn test_backend_step_in_config() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "backend"
+
+            [[pipeline]]
+            name = "matugen"
+            "#,
+        )
+        .unwrap();
+        let ctx = StepContext {
+            backend: Backend::Hyprpaper,
+            ..ctx(false)
+        };
+
+        let steps = build_pipeline(&config, &ctx).unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s.name()).collect();
+
+        assert_eq!(
+            names,
+            vec!["hyprpaper", "hyprpaper", "hyprpaper", "matugen"]
+        );
+    }
+
+    #[test]
+    fn test_config_order_and_enabled() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "wallust"
+
+            [[pipeline]]
+            name = "matugen"
+            enabled = false
+
+            [[pipeline]]
+            name = "swww"
+            "#,
+        )
+        .unwrap();
+
+        let steps = build_pipeline(&config, &ctx(false)).unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s.name()).collect();
+
+        assert_eq!(names, vec!["wallust", "swww"]);
+    }
+
+    #[test]
+    fn test_custom_step_placeholders() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "matugen"
+            args = ["image", "{path}", "--type", "{type}", "--mode", "{light}"]
+            "#,
+        )
+        .unwrap();
+
+        let steps = build_pipeline(&config, &ctx(true)).unwrap();
+
+        match &steps[0].program {
+            Program::Custom {
+                name,
+                program,
+                args,
+            } => {
+                assert_eq!(name, "matugen");
+                assert_eq!(program, "matugen");
+                assert_eq!(
+                    args,
+                    &vec![
+                        "image",
+                        "/test/image.jpg",
+                        "--type",
+                        "scheme-content",
+                        "--mode",
+                        "light"
+                    ]
+                );
+            }
+            _ => panic!("Expected Custom variant"),
+        }
+    }
+
+    #[test]
+    fn test_placeholders_in_values_are_kept() {
+        let path = Path::new("/walls/{type} {light}.jpg");
+        let ctx = StepContext::new(path, "{path}", false, Backend::Swww, DEFAULT_GENERATORS);
+
+        assert_eq!(
+            render_arg("{path}|{type}|{light}|{other}", &ctx),
+            "/walls/{type} {light}.jpg|{path}|dark|{other}"
+        );
+    }
+
+    #[test]
+    fn test_outputs_placeholder() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "swww"
+            args = ["img", "{path}", "--outputs", "{outputs}"]
+            "#,
+        )
+        .unwrap();
+        let outputs = vec!["DP-1".to_string(), "HDMI-A-1".to_string()];
+        let ctx = ctx(false).with_outputs(&outputs);
+
+        let steps = build_pipeline(&config, &ctx).unwrap();
+        match &steps[0].program {
+            Program::Custom { args, .. } => assert_eq!(
+                args,
+                &vec!["img", "/test/image.jpg", "--outputs", "DP-1,HDMI-A-1"]
+            ),
+            _ => panic!("Expected Custom variant"),
+        }
+        assert!(check_custom_steps_take_outputs(&config).is_ok());
+    }
+
+    #[test]
+    fn test_custom_steps_without_outputs() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "swww"
+            args = ["img", "{path}"]
+
+            [[pipeline]]
+            name = "matugen"
+
+            [[pipeline]]
+            name = "notify"
+            program = "notify-send"
+            args = ["Wallpaper changed on {outputs}"]
+
+            [[pipeline]]
+            name = "old"
+            program = "feh"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+
+        let error = check_custom_steps_take_outputs(&config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "With --output every custom step needs {outputs} in its args, otherwise it changes every output. Missing in: `swww`"
+        );
+    }
+
+    #[test]
+    fn test_light_args_only_in_light_mode() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "wal"
+            args = ["-i", "{path}"]
+            light_args = ["-l"]
+            "#,
+        )
+        .unwrap();
+
+        let dark = build_pipeline(&config, &ctx(false)).unwrap();
+        let light = build_pipeline(&config, &ctx(true)).unwrap();
+
+        match (&dark[0].program, &light[0].program) {
+            (Program::Custom { args: dark, .. }, Program::Custom { args: light, .. }) => {
+                assert_eq!(dark, &vec!["-i", "/test/image.jpg"]);
+                assert_eq!(light, &vec!["-i", "/test/image.jpg", "-l"]);
+            }
+            _ => panic!("Expected Custom variants"),
+        }
+    }
+
+    #[test]
+    fn test_parallel_defaults() {
+        let steps = build_pipeline(&Config::default(), &ctx(false)).unwrap();
+        let parallel: Vec<bool> = steps.iter().map(|s| s.parallel).collect();
+
+        assert_eq!(parallel, vec![false, true, true]);
+    }
+
+    #[test]
+    fn test_parallel_from_config() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "backend"
+
+            [[pipeline]]
+            name = "matugen"
+            parallel = false
+
+            [[pipeline]]
+            name = "notify"
+            program = "notify-send"
+            args = ["{path}"]
+            parallel = true
+            "#,
+        )
+        .unwrap();
+
+        let steps = build_pipeline(&config, &ctx(false)).unwrap();
+        let parallel: Vec<bool> = steps.iter().map(|s| s.parallel).collect();
+
+        assert_eq!(parallel, vec![false, false, true]);
+    }
+
+    #[test]
+    fn test_daemon_backend_never_parallel() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "backend"
+            parallel = true
+            "#,
+        )
+        .unwrap();
+        let ctx = StepContext {
+            backend: Backend::Swaybg,
+            ..ctx(false)
+        };
+
+        let steps = build_pipeline(&config, &ctx).unwrap();
+        assert!(!steps[0].parallel);
+    }
+
+    #[test]
+    fn test_group_steps() {
+        let step = |parallel| Step {
+            parallel,
+            ..Step::sequential(Program::custom("x", "true", vec![]))
+        };
+        let steps = vec![step(false), step(true), step(true), step(false), step(true)];
+
+        let sizes: Vec<usize> = group_steps(&steps).iter().map(|g| g.len()).collect();
+        assert_eq!(sizes, vec![1, 2, 1, 1]);
+
+        assert!(group_steps::<Step>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_run_parallel_reports_all_failures() {
+        let steps = vec![
+            Step::parallel(Program::custom("ok", "true", vec![])),
+            Step::parallel(Program::custom("bad", "false", vec![])),
+            Step::parallel(Program::custom(
+                "missing",
+                "wallpaper-no-such-binary",
+                vec![],
+            )),
+        ];
+
+        let error = run_pipeline(&steps, false).unwrap_err().to_string();
+
+        assert!(error.contains("2 of 3 parallel steps failed"));
+        assert!(error.contains("bad:"));
+        assert!(error.contains("missing:"));
+        assert!(!error.contains("ok:"));
+    }
+
+    fn shell(name: &str, script: &str) -> Step {
+        Step::sequential(Program::custom(
+            name,
+            "sh",
+            vec!["-c".into(), script.into()],
+        ))
+    }
+
+    fn write_to(file: &Path, content: &str) -> String {
+        format!("echo {} > '{}'", content, file.display())
+    }
+
+    #[test]
+    fn test_transaction_checks_programs_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let steps = vec![
+            shell("swww", &write_to(&marker, "new")),
+            Step::sequential(Program::custom(
+                "matugen",
+                "wallpaper-no-such-binary",
+                vec![],
+            )),
+        ];
+
+        let error = run_transaction(&steps, None, false)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("wallpaper-no-such-binary"), "{}", error);
+        assert!(error.contains("Nothing was changed"), "{}", error);
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_after_partial_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let screen = dir.path().join("screen");
+        let steps = vec![
+            shell("swww", &write_to(&screen, "new")),
+            shell("matugen", "exit 3"),
+        ];
+        let rollback = Rollback {
+            path: PathBuf::from("/walls/old.jpg"),
+            steps: vec![shell("swww", &write_to(&screen, "old"))],
+        };
+
+        let error = run_transaction(&steps, Some(rollback), false).unwrap_err();
+        let message = error.to_string();
+
+        assert!(message.contains("Step `matugen` failed"), "{}", message);
+        assert!(message.contains("Rolled back"), "{}", message);
+        assert!(message.contains("/walls/old.jpg"), "{}", message);
+        assert_eq!(fs::read_to_string(&screen).unwrap().trim(), "old");
+    }
+
+    #[test]
+    fn test_transaction_reports_failed_rollback() {
+        let steps = vec![shell("swww", "true"), shell("matugen", "false")];
+        let rollback = Rollback {
+            path: PathBuf::from("/walls/old.jpg"),
+            steps: vec![shell("swww", "false")],
+        };
+
+        let message = run_transaction(&steps, Some(rollback), false)
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            message.contains("failed as well at step `swww`"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_transaction_without_changes_does_not_roll_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let screen = dir.path().join("screen");
+        let steps = vec![shell("swww", "false"), shell("matugen", "true")];
+        let rollback = Rollback {
+            path: PathBuf::from("/walls/old.jpg"),
+            steps: vec![shell("swww", &write_to(&screen, "old"))],
+        };
+
+        let message = run_transaction(&steps, Some(rollback), false)
+            .unwrap_err()
+            .to_string();
+
+        assert!(message.contains("Step `swww` failed"), "{}", message);
+        assert!(message.contains("nothing was rolled back"), "{}", message);
+        assert!(!screen.exists());
+    }
+
+    #[test]
+    fn test_parallel_failure_names_failed_steps() {
+        let steps = vec![
+            shell("swww", "true"),
+            Step::parallel(Program::custom("matugen", "true", vec![])),
+            Step::parallel(Program::custom("wallust", "false", vec![])),
+        ];
+
+        let message = run_transaction(&steps, None, false)
+            .unwrap_err()
+            .to_string();
+
+        assert!(message.contains("Step `wallust` failed"), "{}", message);
+        assert!(message.contains("no previous wallpaper"), "{}", message);
+    }
+
+    fn with_policy(step: Step, policy: StepPolicy) -> Step {
+        Step { policy, ..step }
+    }
+
+    #[test]
+    fn test_optional_failure_does_not_stop_the_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let screen = dir.path().join("screen");
+        let steps = vec![
+            shell("swww", "true"),
+            with_policy(shell("wallust", "exit 1"), StepPolicy::Optional),
+            shell("matugen", &write_to(&screen, "palette")),
+        ];
+
+        let report = run_transaction(&steps, None, false).unwrap();
+
+        assert!(screen.exists());
+        assert!(report.is_partial());
+        assert_eq!(report.steps[0], ("swww".to_string(), StepStatus::Succeeded));
+        assert!(matches!(report.steps[1].1, StepStatus::Failed(_)));
+        assert_eq!(report.steps[2].1, StepStatus::Succeeded);
+    }
+
+    #[test]
+    fn test_missing_programs_by_policy() {
+        let missing = |name| Program::custom(name, "wallpaper-no-such-binary", vec![]);
+        let steps = vec![
+            shell("swww", "true"),
+            with_policy(
+                Step::sequential(missing("wallust")),
+                StepPolicy::SkipIfMissing,
+            ),
+            with_policy(Step::sequential(missing("hellwal")), StepPolicy::Optional),
+        ];
+
+        let report = run_transaction(&steps, None, false).unwrap();
+        let statuses: Vec<&StepStatus> = report.steps.iter().map(|(_, s)| s).collect();
+
+        // Reported in pipeline order
+        assert_eq!(statuses[0], &StepStatus::Succeeded);
+        assert!(matches!(statuses[1], StepStatus::Skipped(_)));
+        assert!(matches!(statuses[2], StepStatus::Failed(_)));
+        assert_eq!(report.steps[1].0, "wallust");
+    }
+
+    #[test]
+    fn test_skip_if_missing_is_required_when_installed() {
+        let steps = vec![
+            shell("swww", "true"),
+            with_policy(shell("wallust", "false"), StepPolicy::SkipIfMissing),
+        ];
+
+        let message = run_transaction(&steps, None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Step `wallust` failed"), "{}", message);
+    }
+
+    #[test]
+    fn test_optional_failure_in_parallel_group() {
+        let steps = vec![
+            shell("swww", "true"),
+            Step::parallel(Program::custom("matugen", "true", vec![])),
+            with_policy(
+                Step::parallel(Program::custom("wallust", "false", vec![])),
+                StepPolicy::Optional,
+            ),
+        ];
+
+        let report = run_transaction(&steps, None, false).unwrap();
+        assert!(report.is_partial());
+        assert!(matches!(report.steps[2].1, StepStatus::Failed(_)));
+    }
+
+    #[test]
+    fn test_full_success_is_not_partial() {
+        let report = run_transaction(&[shell("swww", "true")], None, false).unwrap();
+        assert!(!report.is_partial());
+    }
+
+    #[test]
+    fn test_policy_from_config() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "backend"
+
+            [[pipeline]]
+            name = "generators"
+            policy = "skip-if-missing"
+
+            [[pipeline]]
+            name = "notify"
+            program = "notify-send"
+            args = ["{path}"]
+            policy = "optional"
+            "#,
+        )
+        .unwrap();
+
+        let steps = build_pipeline(&config, &ctx(false)).unwrap();
+        let policies: Vec<StepPolicy> = steps.iter().map(|s| s.policy).collect();
+
+        assert_eq!(
+            policies,
+            vec![
+                StepPolicy::Required,
+                StepPolicy::SkipIfMissing,
+                StepPolicy::SkipIfMissing,
+                StepPolicy::Optional
+            ]
+        );
+        assert!(
+            Config::from_toml_str("[[pipeline]]\nname = \"swww\"\npolicy = \"maybe\"").is_err()
+        );
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let defaults = build_pipeline(&Config::default(), &ctx(false)).unwrap();
+        let timeouts: Vec<u64> = defaults.iter().map(|s| s.timeout.as_secs()).collect();
+        assert_eq!(timeouts, vec![15, 60, 60]);
+
+        let config = Config::from_toml_str(
+            r#"
+            step_timeout = "20s"
+
+            [[pipeline]]
+            name = "backend"
+            timeout = "5s"
+
+            [[pipeline]]
+            name = "matugen"
+            "#,
+        )
+        .unwrap();
+
+        let steps = build_pipeline(&config, &ctx(false)).unwrap();
+        let timeouts: Vec<u64> = steps.iter().map(|s| s.timeout.as_secs()).collect();
+        assert_eq!(timeouts, vec![5, 20]);
+    }
+
+    #[test]
+    fn test_step_timeout_fails_the_step() {
+        let step = Step {
+            timeout: Duration::from_millis(200),
+            ..shell("matugen", "sleep 30")
+        };
+
+        let message = run_transaction(&[step], None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Step `matugen` failed"), "{}", message);
+    }
+
+    #[test]
+    fn test_unknown_builtin_step() {
+        let config = Config::from_toml_str(
+            r#"
+            [[pipeline]]
+            name = "unknown"
+            "#,
+        )
+        .unwrap();
+
+        assert!(build_pipeline(&config, &ctx(false)).is_err());
+    }
+}
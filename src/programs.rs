@@ -1,28 +1,93 @@
 use anyhow::Result;
-use std::{ffi::OsString, path::Path};
-
-use crate::utils::run_program_with_dry_run;
+use std::{
+    ffi::OsString,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::config::TemplateConfig;
+use crate::generated::{self, GeneratorRun};
+use crate::generators::Generator;
+use crate::logging::{info, warning};
+use crate::palette;
+use crate::templates;
+use crate::utils::{
+    ProgramOutput, capture_program, capture_program_data, find_in_path, format_command,
+    spawn_detached_with_dry_run,
+};
+
+/// Wallpaper setters return as soon as the image is loaded
+const BACKEND_TIMEOUT: Duration = Duration::from_secs(15);
+/// Palette generators and custom programs may take a while on large images
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub enum Program {
     Matugen {
         path: Box<Path>,
         matugen_type: String,
+        is_light: bool,
     },
     Wallust {
         path: Box<Path>,
         is_light: bool,
     },
+    Pywal {
+        path: Box<Path>,
+        is_light: bool,
+    },
+    Pywal16 {
+        path: Box<Path>,
+        is_light: bool,
+    },
+    Hellwal {
+        path: Box<Path>,
+        is_light: bool,
+    },
+    /// Palette extraction built into wallpaper, runs in-process
+    Native {
+        path: Box<Path>,
+        is_light: bool,
+    },
+    /// Renders the `[[templates]]` of config.toml in-process with the colors of `source`
+    Templates {
+        path: Box<Path>,
+        is_light: bool,
+        templates: Vec<TemplateConfig>,
+        source: Option<Generator>,
+    },
+    /// A palette generator whose colors the `templates` step reads: its run is
+    /// recorded, and matugen prints the colors as JSON
+    TemplateSource(Box<Program>),
     Swww {
         path: Box<Path>,
+        outputs: Vec<String>,
+    },
+    Swaybg {
+        path: Box<Path>,
+    },
+    Wbg {
+        path: Box<Path>,
+    },
+    Feh {
+        path: Box<Path>,
+    },
+    Xwallpaper {
+        path: Box<Path>,
+    },
+    Custom {
+        name: String,
+        program: String,
+        args: Vec<OsString>,
     },
 }
 
 impl Program {
-    pub fn matugen(path: &Path, matugen_type: &str) -> Self {
+    pub fn matugen(path: &Path, matugen_type: &str, is_light: bool) -> Self {
         Self::Matugen {
             path: path.into(),
             matugen_type: matugen_type.to_string(),
+            is_light,
         }
     }
 
@@ -33,27 +98,183 @@ impl Program {
         }
     }
 
+    pub fn pywal(path: &Path, is_light: bool) -> Self {
+        Self::Pywal {
+            path: path.into(),
+            is_light,
+        }
+    }
+
+    pub fn pywal16(path: &Path, is_light: bool) -> Self {
+        Self::Pywal16 {
+            path: path.into(),
+            is_light,
+        }
+    }
+
+    pub fn hellwal(path: &Path, is_light: bool) -> Self {
+        Self::Hellwal {
+            path: path.into(),
+            is_light,
+        }
+    }
+
+    pub fn native(path: &Path, is_light: bool) -> Self {
+        Self::Native {
+            path: path.into(),
+            is_light,
+        }
+    }
+
+    pub fn templates(
+        path: &Path,
+        is_light: bool,
+        templates: &[TemplateConfig],
+        source: Option<Generator>,
+    ) -> Self {
+        Self::Templates {
+            path: path.into(),
+            is_light,
+            templates: templates.to_vec(),
+            source,
+        }
+    }
+
+    /// Let the `templates` step read this generator's colors. `native` saves them
+    /// in palette.json anyway.
+    pub fn template_source(self) -> Self {
+        match self {
+            Program::Native { .. } | Program::TemplateSource(_) => self,
+            _ => Self::TemplateSource(Box::new(self)),
+        }
+    }
+
+    #[allow(dead_code)]
     pub fn swww(path: &Path) -> Self {
-        Self::Swww { path: path.into() }
+        Self::swww_outputs(path, &[])
+    }
+
+    /// swww restricted to the given outputs. Empty means every output.
+    pub fn swww_outputs(path: &Path, outputs: &[String]) -> Self {
+        Self::Swww {
+            path: path.into(),
+            outputs: outputs.to_vec(),
+        }
     }
 
-    fn get_program_name(&self) -> &'static str {
+    pub fn swaybg(path: &Path) -> Self {
+        Self::Swaybg { path: path.into() }
+    }
+
+    pub fn wbg(path: &Path) -> Self {
+        Self::Wbg { path: path.into() }
+    }
+
+    pub fn feh(path: &Path) -> Self {
+        Self::Feh { path: path.into() }
+    }
+
+    pub fn xwallpaper(path: &Path) -> Self {
+        Self::Xwallpaper { path: path.into() }
+    }
+
+    pub fn custom(name: &str, program: &str, args: Vec<OsString>) -> Self {
+        Self::Custom {
+            name: name.to_string(),
+            program: program.to_string(),
+            args,
+        }
+    }
+
+    /// Step name as shown to the user and used in config files
+    pub fn get_step_name(&self) -> &str {
+        match self {
+            Program::Custom { name, .. } => name,
+            // pywal and pywal16 both install a `wal` binary
+            Program::Pywal { .. } => "pywal",
+            Program::Pywal16 { .. } => "pywal16",
+            Program::TemplateSource(program) => program.get_step_name(),
+            _ => self.get_program_name(),
+        }
+    }
+
+    pub fn get_program_name(&self) -> &str {
         match self {
             Program::Matugen { .. } => "matugen",
             Program::Wallust { .. } => "wallust",
+            Program::Pywal { .. } | Program::Pywal16 { .. } => "wal",
+            Program::Hellwal { .. } => "hellwal",
+            Program::Native { .. } => "native",
+            Program::Templates { .. } => "templates",
+            Program::TemplateSource(program) => program.get_program_name(),
             Program::Swww { .. } => "swww",
+            Program::Swaybg { .. } => "swaybg",
+            Program::Wbg { .. } => "wbg",
+            Program::Feh { .. } => "feh",
+            Program::Xwallpaper { .. } => "xwallpaper",
+            Program::Custom { program, .. } => program,
+        }
+    }
+
+    /// Whether the executable can be found, so a pipeline can be checked before it runs
+    pub fn is_installed(&self) -> bool {
+        self.is_builtin() || find_in_path(self.get_program_name()).is_some()
+    }
+
+    /// How long the program may run before it is stopped, unless configured otherwise
+    pub fn default_timeout(&self) -> Duration {
+        match self {
+            Program::Swww { .. }
+            | Program::Swaybg { .. }
+            | Program::Wbg { .. }
+            | Program::Feh { .. }
+            | Program::Xwallpaper { .. } => BACKEND_TIMEOUT,
+            _ => DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The palette generator this program is, if any
+    pub fn generator(&self) -> Option<Generator> {
+        match self {
+            Program::Matugen { .. } => Some(Generator::Matugen),
+            Program::Wallust { .. } => Some(Generator::Wallust),
+            Program::Pywal { .. } => Some(Generator::Pywal),
+            Program::Pywal16 { .. } => Some(Generator::Pywal16),
+            Program::Hellwal { .. } => Some(Generator::Hellwal),
+            Program::Native { .. } => Some(Generator::Native),
+            Program::TemplateSource(program) => program.generator(),
+            _ => None,
         }
     }
 
+    /// Steps that run inside wallpaper instead of starting a program
+    pub fn is_builtin(&self) -> bool {
+        matches!(self, Program::Native { .. } | Program::Templates { .. })
+    }
+
+    /// Programs that keep running to display the wallpaper instead of exiting
+    pub fn is_daemon(&self) -> bool {
+        matches!(self, Program::Swaybg { .. } | Program::Wbg { .. })
+    }
+
     fn get_args(&self) -> Vec<OsString> {
         match self {
-            Program::Matugen { path, matugen_type } => {
-                vec![
+            Program::Matugen {
+                path,
+                matugen_type,
+                is_light,
+            } => {
+                let mut args = vec![
                     OsString::from("image"),
                     path.as_os_str().to_os_string(),
                     OsString::from("--type"),
                     OsString::from(matugen_type),
-                ]
+                ];
+                if *is_light {
+                    args.push(OsString::from("--mode"));
+                    args.push(OsString::from("light"));
+                }
+                args
             }
             Program::Wallust { path, is_light } => {
                 let mut args = vec![
@@ -67,8 +288,42 @@ impl Program {
                 }
                 args
             }
-            Program::Swww { path } => {
-                vec![
+            Program::Pywal { path, is_light } | Program::Pywal16 { path, is_light } => {
+                // -n: leave setting the wallpaper to the backend
+                let mut args = vec![
+                    OsString::from("-i"),
+                    path.as_os_str().to_os_string(),
+                    OsString::from("-n"),
+                ];
+                if matches!(self, Program::Pywal16 { .. }) {
+                    args.push(OsString::from("--cols16"));
+                }
+                if *is_light {
+                    args.push(OsString::from("-l"));
+                }
+                args
+            }
+            Program::Hellwal { path, is_light } => {
+                let mut args = vec![OsString::from("-i"), path.as_os_str().to_os_string()];
+                if *is_light {
+                    args.push(OsString::from("--light"));
+                }
+                args
+            }
+            // Shown in command lines only, the extraction runs in-process
+            Program::Native { path, is_light } => {
+                let mut args = vec![path.as_os_str().to_os_string()];
+                if *is_light {
+                    args.push(OsString::from("--light"));
+                }
+                args
+            }
+            Program::Templates { templates, .. } => templates
+                .iter()
+                .map(|template| template.output.as_os_str().to_os_string())
+                .collect(),
+            Program::Swww { path, outputs } => {
+                let mut args = vec![
                     OsString::from("img"),
                     path.as_os_str().to_os_string(),
                     OsString::from("--transition-type"),
@@ -77,48 +332,131 @@ impl Program {
                     OsString::from("60"),
                     OsString::from("--transition-duration"),
                     OsString::from("1"),
+                ];
+                if !outputs.is_empty() {
+                    args.push(OsString::from("--outputs"));
+                    args.push(OsString::from(outputs.join(",")));
+                }
+                args
+            }
+            Program::Swaybg { path } => {
+                vec![
+                    OsString::from("--mode"),
+                    OsString::from("fill"),
+                    OsString::from("--image"),
+                    path.as_os_str().to_os_string(),
+                ]
+            }
+            Program::Wbg { path } => vec![path.as_os_str().to_os_string()],
+            Program::Feh { path } => {
+                vec![
+                    OsString::from("--no-fehbg"),
+                    OsString::from("--bg-fill"),
+                    path.as_os_str().to_os_string(),
                 ]
             }
+            Program::Xwallpaper { path } => {
+                vec![OsString::from("--zoom"), path.as_os_str().to_os_string()]
+            }
+            Program::TemplateSource(program) => {
+                let mut args = program.get_args();
+                if matches!(**program, Program::Matugen { .. }) {
+                    args.push(OsString::from("--json"));
+                    args.push(OsString::from("hex"));
+                }
+                args
+            }
+            Program::Custom { args, .. } => args.clone(),
         }
     }
 
     #[allow(dead_code)]
     pub fn execute(&self) -> Result<()> {
-        self.execute_with_dry_run(false)
+        self.execute_with_dry_run(false, self.default_timeout())
     }
 
-    pub fn execute_with_dry_run(&self, dry_run: bool) -> Result<()> {
-        let program_name = self.get_program_name();
-        let args = self.get_args();
-        run_program_with_dry_run(program_name, &args, dry_run)
+    /// Full command line, for display
+    pub fn command_line(&self) -> String {
+        format_command(self.get_program_name(), &self.get_args())
     }
-}
-
-#[allow(dead_code)]
-pub fn exec_matugen(path: &Path, matugen_type: &str) -> Result<()> {
-    exec_matugen_with_dry_run(path, matugen_type, false)
-}
-
-pub fn exec_matugen_with_dry_run(path: &Path, matugen_type: &str, dry_run: bool) -> Result<()> {
-    Program::matugen(path, matugen_type).execute_with_dry_run(dry_run)
-}
 
-#[allow(dead_code)]
-pub fn exec_wal(path: &Path, is_light: bool) -> Result<()> {
-    exec_wal_with_dry_run(path, is_light, false)
-}
+    /// Run to completion, streaming the output prefixed with the step name, and
+    /// return it. Not meant for daemon programs, which never exit.
+    pub fn execute_captured(&self, timeout: Duration) -> Result<ProgramOutput> {
+        let (program, args, label) = (
+            self.get_program_name(),
+            self.get_args(),
+            self.get_step_name(),
+        );
+        match self {
+            // matugen's stdout is then the JSON of its colors, not a progress message
+            Program::TemplateSource(inner) if matches!(**inner, Program::Matugen { .. }) => {
+                capture_program_data(program, &args, timeout, label)
+            }
+            _ => capture_program(program, &args, timeout, Some(label)),
+        }
+    }
 
-pub fn exec_wal_with_dry_run(path: &Path, is_light: bool, dry_run: bool) -> Result<()> {
-    Program::wallust(path, is_light).execute_with_dry_run(dry_run)
-}
+    /// Run to completion and fail with the end of the output if the program failed.
+    /// A template source's successful run is recorded for the `templates` step.
+    pub fn execute_checked(&self, timeout: Duration) -> Result<()> {
+        match self {
+            Program::Native { path, is_light } => palette::generate(path, *is_light),
+            Program::Templates {
+                path,
+                is_light,
+                templates,
+                source,
+            } => templates::render_all(path, *is_light, templates, *source),
+            Program::TemplateSource(program) => {
+                let started = SystemTime::now();
+                let output = self.execute_captured(timeout)?;
+                output.check_status()?;
+                program.record_run(started, output);
+                Ok(())
+            }
+            _ => self.execute_captured(timeout)?.check_status(),
+        }
+    }
 
-#[allow(dead_code)]
-pub fn exec_swww(path: &Path) -> Result<()> {
-    exec_swww_with_dry_run(path, false)
-}
+    fn record_run(&self, started: SystemTime, output: ProgramOutput) {
+        let (path, is_light) = match self {
+            Program::Matugen { path, is_light, .. }
+            | Program::Wallust { path, is_light }
+            | Program::Pywal { path, is_light }
+            | Program::Pywal16 { path, is_light }
+            | Program::Hellwal { path, is_light } => (path, *is_light),
+            _ => return,
+        };
+        let Some(generator) = self.generator() else {
+            return;
+        };
+
+        let stdout = matches!(self, Program::Matugen { .. }).then_some(output.stdout);
+        let run = GeneratorRun::new(path, is_light, started, stdout);
+        // The generator did its job; only templates depend on the record
+        if let Err(e) = generated::record(generator, &run) {
+            warning!(
+                "could not record the `{}` run for templates: {:#}",
+                generator.name(),
+                e
+            );
+        }
+    }
 
-pub fn exec_swww_with_dry_run(path: &Path, dry_run: bool) -> Result<()> {
-    Program::swww(path).execute_with_dry_run(dry_run)
+    /// Daemon programs are started in the background, so `timeout` only applies to the
+    /// others. Built-in steps run in-process and are not limited.
+    pub fn execute_with_dry_run(&self, dry_run: bool, timeout: Duration) -> Result<()> {
+        if self.is_daemon() {
+            return spawn_detached_with_dry_run(self.get_program_name(), &self.get_args(), dry_run);
+        }
+        if dry_run {
+            println!("[DRY RUN] Would run: {}", self.command_line());
+            return Ok(());
+        }
+        info!("Running: {}", self.command_line());
+        self.execute_checked(timeout)
+    }
 }
 
 #[cfg(test)]
@@ -129,15 +467,17 @@ mod tests {
     #[test]
     fn test_program_matugen_creation() {
         let path = Path::new("/test/image.jpg");
-        let program = Program::matugen(path, "scheme-tonal-spot");
+        let program = Program::matugen(path, "scheme-tonal-spot", false);
 
         match program {
             Program::Matugen {
                 path: p,
                 matugen_type,
+                is_light,
             } => {
                 assert_eq!(p.as_ref(), Path::new("/test/image.jpg"));
                 assert_eq!(matugen_type, "scheme-tonal-spot");
+                assert!(!is_light);
             }
             _ => panic!("Expected Matugen variant"),
         }
@@ -163,8 +503,9 @@ mod tests {
         let program = Program::swww(path);
 
         match program {
-            Program::Swww { path: p } => {
+            Program::Swww { path: p, outputs } => {
                 assert_eq!(p.as_ref(), Path::new("/test/image.jpg"));
+                assert!(outputs.is_empty());
             }
             _ => panic!("Expected Swww variant"),
         }
@@ -174,15 +515,72 @@ mod tests {
     fn test_program_names() {
         let path = Path::new("/test/image.jpg");
 
-        assert_eq!(Program::matugen(path, "test").get_program_name(), "matugen");
+        assert_eq!(
+            Program::matugen(path, "test", false).get_program_name(),
+            "matugen"
+        );
         assert_eq!(Program::wallust(path, false).get_program_name(), "wallust");
         assert_eq!(Program::swww(path).get_program_name(), "swww");
     }
 
+    #[test]
+    fn test_swww_outputs_args() {
+        let path = Path::new("/test/image.jpg");
+        let outputs = vec!["DP-1".to_string(), "HDMI-A-1".to_string()];
+        let args = Program::swww_outputs(path, &outputs).get_args();
+
+        assert_eq!(args.len(), 10);
+        assert_eq!(args[8], "--outputs");
+        assert_eq!(args[9], "DP-1,HDMI-A-1");
+    }
+
+    #[test]
+    fn test_x11_setter_args() {
+        let path = Path::new("/test/image.jpg");
+
+        assert_eq!(
+            Program::feh(path).get_args(),
+            vec!["--no-fehbg", "--bg-fill", "/test/image.jpg"]
+        );
+        assert_eq!(
+            Program::xwallpaper(path).get_args(),
+            vec!["--zoom", "/test/image.jpg"]
+        );
+        assert!(!Program::feh(path).is_daemon());
+        assert!(!Program::xwallpaper(path).is_daemon());
+    }
+
+    #[test]
+    fn test_wayland_daemon_setter_args() {
+        let path = Path::new("/test/image.jpg");
+
+        assert_eq!(
+            Program::swaybg(path).get_args(),
+            vec!["--mode", "fill", "--image", "/test/image.jpg"]
+        );
+        assert_eq!(Program::wbg(path).get_args(), vec!["/test/image.jpg"]);
+        assert!(Program::swaybg(path).is_daemon());
+        assert!(Program::wbg(path).is_daemon());
+        assert!(!Program::swww(path).is_daemon());
+    }
+
+    #[test]
+    fn test_custom_program() {
+        let program = Program::custom(
+            "notify",
+            "notify-send",
+            vec![OsString::from("Wallpaper"), OsString::from("changed")],
+        );
+
+        assert_eq!(program.get_step_name(), "notify");
+        assert_eq!(program.get_program_name(), "notify-send");
+        assert_eq!(program.get_args(), vec!["Wallpaper", "changed"]);
+    }
+
     #[test]
     fn test_matugen_args() {
         let path = Path::new("/test/image.jpg");
-        let program = Program::matugen(path, "scheme-content");
+        let program = Program::matugen(path, "scheme-content", false);
         let args = program.get_args();
 
         assert_eq!(args.len(), 4);
@@ -192,6 +590,59 @@ mod tests {
         assert_eq!(args[3], "scheme-content");
     }
 
+    #[test]
+    fn test_matugen_args_light() {
+        let path = Path::new("/test/image.jpg");
+        let program = Program::matugen(path, "scheme-content", true);
+        let args = program.get_args();
+
+        assert_eq!(args.len(), 6);
+        assert_eq!(args[4], "--mode");
+        assert_eq!(args[5], "light");
+    }
+
+    #[test]
+    fn test_pywal_args() {
+        let path = Path::new("/test/image.jpg");
+
+        assert_eq!(
+            Program::pywal(path, false).get_args(),
+            vec!["-i", "/test/image.jpg", "-n"]
+        );
+        assert_eq!(
+            Program::pywal(path, true).get_args(),
+            vec!["-i", "/test/image.jpg", "-n", "-l"]
+        );
+        assert_eq!(
+            Program::pywal16(path, true).get_args(),
+            vec!["-i", "/test/image.jpg", "-n", "--cols16", "-l"]
+        );
+    }
+
+    #[test]
+    fn test_pywal_names() {
+        let path = Path::new("/test/image.jpg");
+
+        assert_eq!(Program::pywal(path, false).get_program_name(), "wal");
+        assert_eq!(Program::pywal16(path, false).get_program_name(), "wal");
+        assert_eq!(Program::pywal(path, false).get_step_name(), "pywal");
+        assert_eq!(Program::pywal16(path, false).get_step_name(), "pywal16");
+    }
+
+    #[test]
+    fn test_hellwal_args() {
+        let path = Path::new("/test/image.jpg");
+
+        assert_eq!(
+            Program::hellwal(path, false).get_args(),
+            vec!["-i", "/test/image.jpg"]
+        );
+        assert_eq!(
+            Program::hellwal(path, true).get_args(),
+            vec!["-i", "/test/image.jpg", "--light"]
+        );
+    }
+
     #[test]
     fn test_wallust_args_dark() {
         let path = Path::new("/test/image.jpg");
@@ -234,4 +685,12 @@ mod tests {
         assert_eq!(args[6], "--transition-duration");
         assert_eq!(args[7], "1");
     }
+
+    #[test]
+    fn test_is_installed() {
+        assert!(Program::custom("shell", "sh", vec![]).is_installed());
+        assert!(Program::custom("shell", "/bin/sh", vec![]).is_installed());
+        assert!(!Program::custom("x", "wallpaper-no-such-binary", vec![]).is_installed());
+        assert!(!Program::custom("x", "/nonexistent/sh", vec![]).is_installed());
+    }
 }
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result, anyhow};
+use glob::Pattern;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::logging::warning;
+
+/// Extensions considered images when `--ext` is not given
+pub const DEFAULT_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff", "avif",
+];
+
+#[derive(Debug)]
+pub struct ImageFilter {
+    extensions: Vec<String>,
+    pattern: Option<Pattern>,
+}
+
+impl ImageFilter {
+    pub fn new(extensions: &[String], glob: Option<&str>) -> Result<Self> {
+        let extensions = if extensions.is_empty() {
+            DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+        } else {
+            extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .collect()
+        };
+
+        let pattern = glob
+            .map(Pattern::new)
+            .transpose()
+            .with_context(|| "Invalid glob pattern")?;
+
+        Ok(Self {
+            extensions,
+            pattern,
+        })
+    }
+
+    /// `relative` is the path below the searched directory, used for glob matching
+    fn matches(&self, relative: &Path) -> bool {
+        let extension_ok = relative
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .is_some_and(|e| self.extensions.contains(&e));
+
+        let pattern_ok = self
+            .pattern
+            .as_ref()
+            .is_none_or(|p| p.matches_path(relative));
+
+        extension_ok && pattern_ok
+    }
+}
+
+/// Recursively collect matching images below `dir`, sorted for a stable order.
+/// Paths are canonical, like the ones in the history, so `--avoid` can compare
+/// them. Subdirectories that cannot be read are skipped with a warning.
+pub fn collect_images(dir: &Path, filter: &ImageFilter) -> Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    walk(dir, dir, filter, &mut images)?;
+    images.sort();
+    // Several symlinks may lead to the same image
+    images.dedup();
+    Ok(images)
+}
+
+fn walk(root: &Path, dir: &Path, filter: &ImageFilter, images: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warning!("Skipping an entry in {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                warning!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        // Symlinked directories are not followed to avoid cycles
+        if file_type.is_dir() {
+            if let Err(e) = walk(root, &path, filter, images) {
+                warning!("Skipping {}: {:#}", path.display(), e);
+            }
+        } else if path.is_file() {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if !filter.matches(relative) {
+                continue;
+            }
+            match path.canonicalize() {
+                Ok(canonical) => images.push(canonical),
+                Err(e) => warning!("Skipping {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Pick a random image that is not among `recent`. Falls back to every
+/// candidate when all of them were used recently.
+pub fn pick_image<'a>(
+    candidates: &'a [PathBuf],
+    recent: &[PathBuf],
+    rng: &mut fastrand::Rng,
+) -> Result<&'a PathBuf> {
+    if candidates.is_empty() {
+        return Err(anyhow!("No images found."));
+    }
+
+    let fresh: Vec<&PathBuf> = candidates.iter().filter(|c| !recent.contains(c)).collect();
+
+    if fresh.is_empty() {
+        Ok(&candidates[rng.usize(..candidates.len())])
+    } else {
+        Ok(fresh[rng.usize(..fresh.len())])
+    }
+}
+
+pub fn new_rng(seed: Option<u64>) -> fastrand::Rng {
+    match seed {
+        Some(seed) => fastrand::Rng::with_seed(seed),
+        None => fastrand::Rng::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn setup_library() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            "a.jpg",
+            "b.PNG",
+            "notes.txt",
+            "nature/forest.jpg",
+            "nature/deep/lake.webp",
+        ];
+        for file in files {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        dir
+    }
+
+    fn names(root: &Path, images: &[PathBuf]) -> Vec<String> {
+        let root = root.canonicalize().unwrap();
+        images
+            .iter()
+            .map(|p| {
+                p.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_collect_images_recursive() {
+        let dir = setup_library();
+        let filter = ImageFilter::new(&[], None).unwrap();
+        let images = collect_images(dir.path(), &filter).unwrap();
+
+        assert_eq!(
+            names(dir.path(), &images),
+            vec![
+                "a.jpg",
+                "b.PNG",
+                "nature/deep/lake.webp",
+                "nature/forest.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_images_extension_filter() {
+        let dir = setup_library();
+        let filter = ImageFilter::new(&[".png".to_string(), "webp".to_string()], None).unwrap();
+        let images = collect_images(dir.path(), &filter).unwrap();
+
+        assert_eq!(
+            names(dir.path(), &images),
+            vec!["b.PNG", "nature/deep/lake.webp"]
+        );
+    }
+
+    #[test]
+    fn test_collect_images_glob_filter() {
+        let dir = setup_library();
+        let filter = ImageFilter::new(&[], Some("nature/*")).unwrap();
+        let images = collect_images(dir.path(), &filter).unwrap();
+
+        assert_eq!(
+            names(dir.path(), &images),
+            vec!["nature/deep/lake.webp", "nature/forest.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_collect_images_canonical() {
+        let dir = setup_library();
+        let elsewhere = TempDir::new().unwrap();
+        let target = elsewhere.path().join("city.png");
+        fs::write(&target, b"").unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join("nature/city.png")).unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join("nature/deep/city.png")).unwrap();
+
+        let filter = ImageFilter::new(&["png".to_string()], None).unwrap();
+        let relative = dir.path().join("nature/../nature");
+        let images = collect_images(&relative, &filter).unwrap();
+
+        assert_eq!(images, vec![target.canonicalize().unwrap()]);
+    }
+
+    #[test]
+    fn test_collect_images_skips_unreadable_directory() {
+        // Permissions do not stop root from reading
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let dir = setup_library();
+        let locked = dir.path().join("nature/deep");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let filter = ImageFilter::new(&[], None).unwrap();
+        let images = collect_images(dir.path(), &filter);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(
+            names(dir.path(), &images.unwrap()),
+            vec!["a.jpg", "b.PNG", "nature/forest.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_pick_image_avoids_recent() {
+        let candidates = vec![PathBuf::from("/a.jpg"), PathBuf::from("/b.jpg")];
+        let recent = vec![PathBuf::from("/a.jpg")];
+
+        for seed in 0..20 {
+            let mut rng = new_rng(Some(seed));
+            let picked = pick_image(&candidates, &recent, &mut rng).unwrap();
+            assert_eq!(picked, &PathBuf::from("/b.jpg"));
+        }
+    }
+
+    #[test]
+    fn test_pick_image_falls_back_when_all_recent() {
+        let candidates = vec![PathBuf::from("/a.jpg")];
+        let mut rng = new_rng(Some(1));
+
+        let picked = pick_image(&candidates, &candidates, &mut rng).unwrap();
+        assert_eq!(picked, &PathBuf::from("/a.jpg"));
+    }
+
+    #[test]
+    fn test_pick_image_seed_is_reproducible() {
+        let candidates: Vec<PathBuf> = (0..100)
+            .map(|i| PathBuf::from(format!("/{}.jpg", i)))
+            .collect();
+
+        let first = pick_image(&candidates, &[], &mut new_rng(Some(42))).unwrap();
+        let second = pick_image(&candidates, &[], &mut new_rng(Some(42))).unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_pick_image_empty() {
+        assert!(pick_image(&[], &[], &mut new_rng(Some(1))).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often waits check whether a signal arrived
+pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of the last SIGINT/SIGTERM received, 0 while none. Only one is ever
+/// acted on, since a second signal exits right away.
+static RECEIVED: LazyLock<Arc<AtomicUsize>> = LazyLock::new(Default::default);
+static STOPPING: LazyLock<Arc<AtomicBool>> = LazyLock::new(Default::default);
+
+/// Turn SIGINT and SIGTERM into a flag checked by running programs and the
+/// daemon, so child processes are stopped instead of left orphaned. A second
+/// signal exits right away.
+pub fn install() -> Result<()> {
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(
+            signal,
+            128 + signal,
+            Arc::clone(&STOPPING),
+        )
+        .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&STOPPING)))
+        .and_then(|_| {
+            signal_hook::flag::register_usize(signal, Arc::clone(&RECEIVED), signal as usize)
+        })
+        .with_context(|| format!("Failed to install handler for signal {}", signal))?;
+    }
+    Ok(())
+}
+
+/// The signal that asked us to stop, if any
+pub fn received() -> Option<i32> {
+    match RECEIVED.load(Ordering::Relaxed) {
+        0 => None,
+        signal => Some(signal as i32),
+    }
+}
+
+/// Sleep for `duration`. Returns false when interrupted by a signal.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if received().is_some() {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::history::get_data_directory;
+use crate::utils::{lock_exclusive, with_suffix, write_atomic};
+
+const TAGS_FILENAME: &str = "tags.json";
+
+/// Free-form tags per image, kept next to history.json. Tags belong to the
+/// image file rather than a history entry, so they survive history eviction.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TagStore {
+    tags: BTreeMap<PathBuf, BTreeSet<String>>,
+}
+
+impl TagStore {
+    pub fn load() -> Result<Self> {
+        Self::read_at(&get_tags_file_path()?)
+    }
+
+    /// Read-modify-write tags.json under its lock, so concurrent `tag` runs do
+    /// not lose each other's changes. Returns the store as written.
+    pub fn update(modify: impl FnOnce(&mut Self) -> Result<()>) -> Result<Self> {
+        Self::update_at(&get_tags_file_path()?, modify)
+    }
+
+    fn update_at(path: &Path, modify: impl FnOnce(&mut Self) -> Result<()>) -> Result<Self> {
+        let _lock = lock_exclusive(&with_suffix(path, ".lock"))?;
+
+        let mut store = Self::read_at(path)?;
+        modify(&mut store)?;
+        store.write_at(path)?;
+
+        Ok(store)
+    }
+
+    fn read_at(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tags file: {}", path.display()))?;
+
+        serde_json::from_str(&content).with_context(|| "Failed to parse tags file")
+    }
+
+    fn write_at(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create data directory: {}", parent.display())
+            })?;
+        }
+
+        let content =
+            serde_json::to_string_pretty(self).with_context(|| "Failed to serialize tags")?;
+
+        write_atomic(path, content.as_bytes())
+            .with_context(|| format!("Failed to write tags file: {}", path.display()))
+    }
+
+    pub fn add(&mut self, path: &Path, tags: &[String]) -> Result<()> {
+        let tags = normalize_tags(tags)?;
+        self.tags
+            .entry(path.to_path_buf())
+            .or_default()
+            .extend(tags);
+        Ok(())
+    }
+
+    /// Fails when `path` has none of `tags`, so a mistyped path or tag is noticed
+    pub fn remove(&mut self, path: &Path, tags: &[String]) -> Result<()> {
+        let tags = normalize_tags(tags)?;
+        let current = self
+            .tags
+            .get_mut(path)
+            .filter(|current| tags.iter().any(|t| current.contains(t)))
+            .ok_or_else(|| anyhow!("{} is not tagged {}", path.display(), tags.join(", ")))?;
+
+        current.retain(|t| !tags.contains(t));
+        if current.is_empty() {
+            self.tags.remove(path);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&BTreeSet<String>> {
+        self.tags.get(path)
+    }
+
+    /// Whether `path` has every one of `tags` (already normalized)
+    pub fn has_all(&self, path: &Path, tags: &[String]) -> bool {
+        tags.is_empty()
+            || self
+                .tags
+                .get(path)
+                .is_some_and(|t| tags.iter().all(|tag| t.contains(tag)))
+    }
+
+    /// Every tag with the number of images carrying it
+    pub fn counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for tag in self.tags.values().flatten() {
+            *counts.entry(tag.as_str()).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// Tags are matched case-insensitively and may not be empty or contain commas,
+/// since `--tag` takes a comma-separated list
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+    tags.iter()
+        .map(|tag| {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() {
+                Err(anyhow!("Tags must not be empty"))
+            } else if tag.contains(',') {
+                Err(anyhow!("Tags must not contain commas: {}", tag))
+            } else {
+                Ok(tag)
+            }
+        })
+        .collect()
+}
+
+fn get_tags_file_path() -> Result<PathBuf> {
+    let data_dir = get_data_directory()?;
+    Ok(data_dir.join(TAGS_FILENAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_add_and_remove_tags() {
+        let mut store = TagStore::default();
+        let path = Path::new("/walls/forest.jpg");
+
+        store.add(path, &tags(&["Nature", " dark "])).unwrap();
+        store.add(path, &tags(&["nature"])).unwrap();
+        assert_eq!(
+            store.get(path).unwrap().iter().collect::<Vec<_>>(),
+            vec!["dark", "nature"]
+        );
+
+        store.remove(path, &tags(&["DARK"])).unwrap();
+        assert_eq!(store.get(path).unwrap().len(), 1);
+
+        store.remove(path, &tags(&["nature"])).unwrap();
+        assert!(store.get(path).is_none());
+
+        // Nothing to remove
+        store.add(path, &tags(&["dark"])).unwrap();
+        let error = store.remove(path, &tags(&["nature"])).unwrap_err();
+        assert_eq!(error.to_string(), "/walls/forest.jpg is not tagged nature");
+        let other = Path::new("walls/forest.jpg");
+        assert!(store.remove(other, &tags(&["dark"])).is_err());
+        assert_eq!(store.get(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_has_all() {
+        let mut store = TagStore::default();
+        let forest = Path::new("/walls/forest.jpg");
+        store.add(forest, &tags(&["nature", "dark"])).unwrap();
+
+        assert!(store.has_all(forest, &tags(&["nature"])));
+        assert!(store.has_all(forest, &tags(&["nature", "dark"])));
+        assert!(!store.has_all(forest, &tags(&["nature", "work"])));
+        assert!(!store.has_all(Path::new("/walls/city.jpg"), &tags(&["nature"])));
+        assert!(store.has_all(Path::new("/walls/city.jpg"), &[]));
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut store = TagStore::default();
+        store.add(Path::new("/a.jpg"), &tags(&["nature"])).unwrap();
+        store
+            .add(Path::new("/b.jpg"), &tags(&["nature", "work"]))
+            .unwrap();
+
+        let counts = store.counts();
+        assert_eq!(counts["nature"], 2);
+        assert_eq!(counts["work"], 1);
+    }
+
+    #[test]
+    fn test_invalid_tags() {
+        assert!(normalize_tags(&tags(&["  "])).is_err());
+        assert!(normalize_tags(&tags(&["a,b"])).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_updates_do_not_lose_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tags.json");
+
+        std::thread::scope(|scope| {
+            for writer in 0..4 {
+                let path = &path;
+                scope.spawn(move || {
+                    for i in 0..10 {
+                        let image = PathBuf::from(format!("/walls/{}-{}.jpg", writer, i));
+                        TagStore::update_at(path, |store| store.add(&image, &tags(&["nature"])))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let store = TagStore::read_at(&path).unwrap();
+        assert_eq!(store.counts()["nature"], 40);
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(
+            names
+                .iter()
+                .all(|name| !name.to_string_lossy().ends_with(".tmp"))
+        );
+    }
+
+    #[test]
+    fn test_serialize_deserialize() {
+        let mut store = TagStore::default();
+        store.add(Path::new("/a.jpg"), &tags(&["nature"])).unwrap();
+
+        let json = serde_json::to_string(&store).unwrap();
+        assert_eq!(json, r#"{"tags":{"/a.jpg":["nature"]}}"#);
+        assert_eq!(serde_json::from_str::<TagStore>(&json).unwrap(), store);
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result, anyhow};
+use std::{fs, path::Path};
+
+use crate::config::TemplateConfig;
+use crate::generated;
+use crate::generators::Generator;
+use crate::logging::{self, info};
+use crate::palette::{Color, Scheme};
+use crate::signals;
+use crate::utils::{expand_tilde, write_atomic};
+
+/// What a template can refer to
+pub struct Variables<'a> {
+    pub wallpaper: &'a Path,
+    pub is_light: bool,
+    pub scheme: &'a Scheme,
+}
+
+/// A variable's value while filters are applied
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Color(Color),
+    Text(String),
+}
+
+impl Value {
+    fn into_text(self) -> String {
+        match self {
+            Value::Color(color) => color.hex(),
+            Value::Text(text) => text,
+        }
+    }
+}
+
+impl Variables<'_> {
+    fn get(&self, name: &str) -> Option<Value> {
+        let scheme = self.scheme;
+        let color = match name {
+            "wallpaper" => return Some(Value::Text(self.wallpaper.display().to_string())),
+            "mode" => {
+                let mode = if self.is_light { "light" } else { "dark" };
+                return Some(Value::Text(mode.to_string()));
+            }
+            "background" => scheme.background,
+            "foreground" => scheme.foreground,
+            "cursor" => scheme.cursor,
+            "accent" => scheme.accent,
+            _ => {
+                let index: usize = name.strip_prefix("color")?.parse().ok()?;
+                *scheme.colors.get(index)?
+            }
+        };
+        Some(Value::Color(color))
+    }
+}
+
+/// Replace every `{{ name | filter(arg) | ... }}` in `template`. Unknown names
+/// and filters are errors, so typos do not end up in the written file.
+pub fn render(template: &str, vars: &Variables) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let offset = template.len() - rest.len() + start;
+        let line = template[..offset].matches('\n').count() + 1;
+        output.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("line {}: `{{{{` is never closed with `}}}}`", line))?;
+        let value = evaluate(after[..end].trim(), vars)
+            .with_context(|| format!("line {}: in `{{{{{}}}}}`", line, &after[..end]))?;
+        output.push_str(&value);
+
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// `name | filter | filter(arg)`
+fn evaluate(expression: &str, vars: &Variables) -> Result<String> {
+    let mut parts = expression.split('|').map(str::trim);
+    let name = parts.next().unwrap_or_default();
+    let mut value = vars
+        .get(name)
+        .ok_or_else(|| anyhow!("unknown variable `{}`", name))?;
+
+    for filter in parts {
+        value = apply_filter(value, filter)?;
+    }
+    Ok(value.into_text())
+}
+
+fn apply_filter(value: Value, filter: &str) -> Result<Value> {
+    let (name, arg) = match filter.split_once('(') {
+        Some((name, arg)) => {
+            let arg = arg
+                .strip_suffix(')')
+                .ok_or_else(|| anyhow!("filter `{}` is missing `)`", filter))?;
+            (name.trim(), Some(arg.trim()))
+        }
+        None => (filter, None),
+    };
+
+    let percent = || -> Result<f32> {
+        let arg =
+            arg.ok_or_else(|| anyhow!("filter `{}` needs an amount, e.g. {}(10)", name, name))?;
+        arg.parse()
+            .map_err(|_| anyhow!("filter `{}` expects a number, got `{}`", name, arg))
+    };
+    let color = |value: &Value| match value {
+        Value::Color(color) => Ok(*color),
+        Value::Text(_) => Err(anyhow!("filter `{}` only works on colors", name)),
+    };
+
+    match name {
+        "lighten" => Ok(Value::Color(color(&value)?.lighten(percent()?))),
+        "darken" => Ok(Value::Color(color(&value)?.darken(percent()?))),
+        "strip" => Ok(Value::Text(color(&value)?.hex().split_off(1))),
+        "rgb" => {
+            let color = color(&value)?;
+            Ok(Value::Text(format!("{},{},{}", color.r, color.g, color.b)))
+        }
+        "upper" => Ok(Value::Text(value.into_text().to_uppercase())),
+        _ => Err(anyhow!("unknown filter `{}`", name)),
+    }
+}
+
+/// The `templates` step: render every template with the colors `source` generated
+/// for `wallpaper`. All templates are rendered before any output is written, so a
+/// broken one leaves every file as it was.
+pub fn render_all(
+    wallpaper: &Path,
+    is_light: bool,
+    templates: &[TemplateConfig],
+    source: Option<Generator>,
+) -> Result<()> {
+    if let Some(signal) = signals::received() {
+        return Err(anyhow!(
+            "Not rendering templates: interrupted by signal {}",
+            signal
+        ));
+    }
+
+    let source = source.ok_or_else(|| {
+        anyhow!(
+            "No palette generator runs, so templates have no colors. Add one or set `template_colors` in config.toml."
+        )
+    })?;
+    let (scheme, origin) = generated::scheme_for(wallpaper, is_light, source)
+        .with_context(|| format!("Failed to read the colors of `{}`", source.name()))?;
+    info!("[templates] Using colors from {}", origin);
+    let vars = Variables {
+        wallpaper,
+        is_light,
+        scheme: &scheme,
+    };
+
+    let mut rendered = Vec::with_capacity(templates.len());
+    for template in templates {
+        let input = expand_tilde(&template.input);
+        let content = fs::read_to_string(&input)
+            .with_context(|| format!("Failed to read template: {}", input.display()))?;
+        let output = render(&content, &vars)
+            .with_context(|| format!("Failed to render template: {}", input.display()))?;
+        rendered.push((expand_tilde(&template.output), output));
+    }
+
+    for (path, content) in &rendered {
+        write_output(path, content)?;
+        info!("[templates] Wrote {}", path.display());
+    }
+    logging::record(&format!(
+        "rendered {} templates with colors from {}",
+        rendered.len(),
+        origin
+    ));
+    Ok(())
+}
+
+fn write_output(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    write_atomic(path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn scheme() -> Scheme {
+        let mut colors = [Color::new(0, 0, 0); 16];
+        for (index, color) in colors.iter_mut().enumerate() {
+            *color = Color::new(index as u8 * 16, 0x80, 0x40);
+        }
+        Scheme {
+            background: Color::from_hex("#101118").unwrap(),
+            foreground: Color::from_hex("#e1e4ef").unwrap(),
+            cursor: Color::from_hex("#ff828b").unwrap(),
+            accent: Color::from_hex("#3a6fd8").unwrap(),
+            colors,
+        }
+    }
+
+    fn render_with(template: &str) -> Result<String> {
+        let scheme = scheme();
+        let vars = Variables {
+            wallpaper: Path::new("/walls/forest.png"),
+            is_light: false,
+            scheme: &scheme,
+        };
+        render(template, &vars)
+    }
+
+    #[test]
+    fn test_render_variables() {
+        assert_eq!(
+            render_with("bg={{background}} fg={{ foreground }}\ncolor15={{color15}}").unwrap(),
+            "bg=#101118 fg=#e1e4ef\ncolor15=#f08040"
+        );
+        assert_eq!(
+            render_with("{{wallpaper}} {{mode}} {{color0}}{{accent}}").unwrap(),
+            "/walls/forest.png dark #008040#3a6fd8"
+        );
+        assert_eq!(
+            render_with("no variables { } }}").unwrap(),
+            "no variables { } }}"
+        );
+    }
+
+    #[test]
+    fn test_render_filters() {
+        let accent = Color::from_hex("#3a6fd8").unwrap();
+        assert_eq!(
+            render_with("{{accent | lighten(10)}}").unwrap(),
+            accent.lighten(10.0).hex()
+        );
+        assert_eq!(
+            render_with("{{ accent|darken( 5 )|strip }}").unwrap(),
+            accent.darken(5.0).hex()[1..]
+        );
+        assert_eq!(render_with("{{accent | rgb}}").unwrap(), "58,111,216");
+        assert_eq!(render_with("{{accent | strip | upper}}").unwrap(), "3A6FD8");
+        assert_eq!(render_with("{{mode | upper}}").unwrap(), "DARK");
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let error = |template| format!("{:#}", render_with(template).unwrap_err());
+
+        assert!(error("a\n{{colr0}}").contains("line 2"));
+        assert!(error("{{colr0}}").contains("unknown variable `colr0`"));
+        assert!(error("{{color16}}").contains("unknown variable `color16`"));
+        assert!(error("{{accent | blur}}").contains("unknown filter `blur`"));
+        assert!(error("{{accent | lighten}}").contains("needs an amount"));
+        assert!(error("{{accent | lighten(much)}}").contains("expects a number"));
+        assert!(error("{{accent | lighten(10}}").contains("missing `)`"));
+        assert!(error("{{wallpaper | lighten(10)}}").contains("only works on colors"));
+        assert!(error("x {{accent").contains("never closed"));
+    }
+
+    #[test]
+    fn test_write_output() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("kitty/colors.conf");
+
+        write_output(&path, "background #101118\n").unwrap();
+        write_output(&path, "background #f2f5ff\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "background #f2f5ff\n");
+        assert!(!dir.path().join("kitty/colors.conf.tmp").exists());
+    }
+}
@@ -1,17 +1,33 @@
 use anyhow::{Context, Result, anyhow};
 use std::{
     ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-pub fn normalize_and_check_path(original: &Path) -> Result<PathBuf> {
-    let expanded = {
-        let s = original.to_string_lossy();
-        shellexpand::tilde(&s).into_owned()
-    };
+use crate::logging::{self, Verbosity, info, verbose};
+use crate::signals::{self, POLL_INTERVAL};
+
+/// Numbers the temporary files of [`write_atomic`] within this process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Time a stopped program gets to exit after SIGTERM before it is killed
+const KILL_GRACE: Duration = Duration::from_secs(1);
+/// Output lines of a failed program quoted in the error
+const ERROR_OUTPUT_LINES: usize = 20;
+
+pub fn expand_tilde(original: &Path) -> PathBuf {
+    let s = original.to_string_lossy();
+    PathBuf::from(shellexpand::tilde(&s).into_owned())
+}
 
-    let pb = PathBuf::from(expanded);
+pub fn normalize_and_check_path(original: &Path) -> Result<PathBuf> {
+    let pb = expand_tilde(original);
 
     if !pb.exists() {
         return Err(anyhow!("Path does not exist: {}", pb.display()));
@@ -27,55 +43,662 @@ pub fn normalize_and_check_path(original: &Path) -> Result<PathBuf> {
     Ok(canonical)
 }
 
+pub fn normalize_and_check_dir(original: &Path) -> Result<PathBuf> {
+    let pb = expand_tilde(original);
+
+    if !pb.exists() {
+        return Err(anyhow!("Path does not exist: {}", pb.display()));
+    }
+    if !pb.is_dir() {
+        return Err(anyhow!("Path is not a directory: {}", pb.display()));
+    }
+
+    let canonical = pb
+        .canonicalize()
+        .with_context(|| format!("Failed to canonicalize path: {}", pb.display()))?;
+
+    Ok(canonical)
+}
+
+/// `path` with `suffix` appended to the file name, e.g. history.json -> history.json.bak
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Take an exclusive advisory lock on `path` (created if needed), blocking
+/// until it is available. The lock is released when the returned file is dropped.
+pub fn lock_exclusive(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file: {}", path.display()))?;
+
+    file.lock()
+        .with_context(|| format!("Failed to lock: {}", path.display()))?;
+
+    Ok(file)
+}
+
+/// Replace `path` with `content` so that readers see either the old or the new
+/// file, never a partially written one. A symlink is followed, so the file it points
+/// to is replaced (e.g. in stow-managed dotfiles), and an existing file keeps its mode.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let permissions = fs::metadata(&path).ok().map(|m| m.permissions());
+
+    // Unique per writer, so concurrent runs never write into each other's file
+    let tmp_path = with_suffix(
+        &path,
+        &format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+
+    let result = write_new(&tmp_path, content, permissions).and_then(|_| {
+        fs::rename(&tmp_path, &path).with_context(|| {
+            format!(
+                "Failed to replace {} with {}",
+                path.display(),
+                tmp_path.display()
+            )
+        })
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn write_new(path: &Path, content: &[u8], permissions: Option<fs::Permissions>) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create file: {}", path.display()))?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)
+            .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    }
+    file.write_all(content)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Failed to write file: {}", path.display()))
+}
+
 #[allow(dead_code)]
-pub fn run_program(program: &str, args: &[OsString]) -> Result<()> {
-    run_program_with_dry_run(program, args, false)
+pub fn run_program(program: &str, args: &[OsString], timeout: Duration) -> Result<()> {
+    run_program_with_dry_run(program, args, false, timeout, program)
+}
+
+/// Run a program, streaming its output with a `[label]` prefix on every line
+pub fn run_program_with_dry_run(
+    program: &str,
+    args: &[OsString],
+    dry_run: bool,
+    timeout: Duration,
+    label: &str,
+) -> Result<()> {
+    if dry_run {
+        println!("[DRY RUN] Would run: {}", format_command(program, args));
+        return Ok(());
+    }
+
+    info!("Running: {}", format_command(program, args));
+
+    capture_program(program, args, timeout, Some(label))?.check_status()
+}
+
+/// Captured result of a finished program
+#[derive(Debug)]
+pub struct ProgramOutput {
+    pub program: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub status: ExitStatus,
+}
+
+impl ProgramOutput {
+    /// Fails with the end of the program's output, preferably stderr, so the
+    /// reason is visible in the error report
+    pub fn check_status(&self) -> Result<()> {
+        if self.status.success() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "Command `{}` exited with status: {}",
+            self.program, self.status
+        );
+
+        let output = if self.stderr.trim().is_empty() {
+            &self.stdout
+        } else {
+            &self.stderr
+        };
+        let lines: Vec<&str> = output.lines().filter(|l| !l.trim().is_empty()).collect();
+        if !lines.is_empty() {
+            let skipped = lines.len().saturating_sub(ERROR_OUTPUT_LINES);
+            message.push_str("\nOutput:");
+            if skipped > 0 {
+                message.push_str(&format!("\n    ({} earlier lines omitted)", skipped));
+            }
+            for line in &lines[skipped..] {
+                message.push_str("\n    ");
+                message.push_str(line);
+            }
+        }
+
+        Err(anyhow!(message))
+    }
+}
+
+/// Run a program to completion and collect its output. With a `label`, every
+/// line is also printed as soon as it arrives, prefixed with `[label]`, unless
+/// `-q` was given. The program runs in its own process group, which is stopped
+/// as a whole when `timeout` expires or SIGINT/SIGTERM arrives.
+/// Every run is recorded in the log file together with its output.
+pub fn capture_program(
+    program: &str,
+    args: &[OsString],
+    timeout: Duration,
+    label: Option<&str>,
+) -> Result<ProgramOutput> {
+    capture(program, args, timeout, label, true)
+}
+
+/// Like [`capture_program`] for programs whose stdout is data rather than
+/// messages; only their stderr is shown
+pub fn capture_program_data(
+    program: &str,
+    args: &[OsString],
+    timeout: Duration,
+    label: &str,
+) -> Result<ProgramOutput> {
+    capture(program, args, timeout, Some(label), false)
+}
+
+fn capture(
+    program: &str,
+    args: &[OsString],
+    timeout: Duration,
+    label: Option<&str>,
+    echo_stdout: bool,
+) -> Result<ProgramOutput> {
+    let started = Instant::now();
+    let echo = label.filter(|_| logging::enabled(Verbosity::Normal));
+    let result = run_and_capture(program, args, timeout, echo, echo_stdout);
+    let elapsed = started.elapsed();
+
+    if let Ok(output) = &result {
+        verbose!(
+            "`{}` finished with {} in {}",
+            label.unwrap_or(program),
+            output.status,
+            humantime::format_duration(logging::round_to_millis(elapsed))
+        );
+    }
+    logging::record_program(&format_command(program, args), &result, elapsed);
+    result
+}
+
+fn run_and_capture(
+    program: &str,
+    args: &[OsString],
+    timeout: Duration,
+    label: Option<&str>,
+    echo_stdout: bool,
+) -> Result<ProgramOutput> {
+    if let Some(signal) = signals::received() {
+        return Err(anyhow!(
+            "Not starting `{}`: interrupted by signal {}",
+            program,
+            signal
+        ));
+    }
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .with_context(|| {
+            format!(
+                "Failed to spawn command `{}` (is it installed and in PATH?)",
+                program
+            )
+        })?;
+
+    let echo = |stream| label.map(|label| (label.to_string(), stream));
+    let stdout = read_in_background(
+        child.stdout.take(),
+        echo(Stream::Stdout).filter(|_| echo_stdout),
+    );
+    let stderr = read_in_background(child.stderr.take(), echo(Stream::Stderr));
+
+    // A program may leave something running in the background that holds the pipes
+    // open after it exited, so its output is awaited against the same deadline
+    let deadline = Instant::now() + timeout;
+    let mut exited = None;
+    let status = loop {
+        if exited.is_none() {
+            exited = child
+                .try_wait()
+                .with_context(|| format!("Failed to wait for command `{}`", program))?;
+        }
+        if let Some(status) = exited.filter(|_| stdout.is_finished() && stderr.is_finished()) {
+            break status;
+        }
+        // Output readers are left behind: a stray grandchild may still hold the pipes open
+        if let Some(signal) = signals::received() {
+            stop_process_group(&mut child);
+            return Err(anyhow!(
+                "Stopped `{}`: interrupted by signal {}",
+                program,
+                signal
+            ));
+        }
+        if Instant::now() >= deadline {
+            stop_process_group(&mut child);
+            if exited.is_some() {
+                return Err(anyhow!(
+                    "Command `{}` timed out after {}: it exited, but a process it started kept its output open and was stopped",
+                    program,
+                    humantime::format_duration(timeout)
+                ));
+            }
+            return Err(anyhow!(
+                "Command `{}` timed out after {} and was stopped",
+                program,
+                humantime::format_duration(timeout)
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(ProgramOutput {
+        program: program.to_string(),
+        stdout: String::from_utf8_lossy(&stdout.join().unwrap_or_default()).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned(),
+        status,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Collect everything from `pipe`, echoing each line to the matching stream
+/// when `echo` is given. Lines are written whole, so concurrent programs
+/// interleave by line and never mid-line.
+fn read_in_background(
+    pipe: Option<impl Read + Send + 'static>,
+    echo: Option<(String, Stream)>,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut captured = Vec::new();
+        let Some(pipe) = pipe else {
+            return captured;
+        };
+
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            if let Some((label, stream)) = &echo {
+                let text = prefixed_line(label, &line);
+                // A closed terminal must not stop the program or lose its captured output
+                let _ = match stream {
+                    Stream::Stdout => io::stdout().lock().write_all(text.as_bytes()),
+                    Stream::Stderr => io::stderr().lock().write_all(text.as_bytes()),
+                };
+            }
+            captured.extend_from_slice(&line);
+        }
+        captured
+    })
+}
+
+/// `[label] line` with exactly one trailing newline
+fn prefixed_line(label: &str, line: &[u8]) -> String {
+    let text = String::from_utf8_lossy(line);
+    let text = text.trim_end_matches(['\n', '\r']);
+    if text.is_empty() {
+        return format!("[{}]\n", label);
+    }
+    format!("[{}] {}\n", label, text)
+}
+
+/// SIGTERM the child's process group, then SIGKILL whatever is left after a
+/// grace period, so programs it started do not outlive it
+fn stop_process_group(child: &mut Child) {
+    let group = -(child.id() as libc::pid_t);
+
+    // SAFETY: kill only sends a signal. The group is ours: either the child is not
+    // reaped yet, or a member it left behind keeps the group id reserved.
+    unsafe { libc::kill(group, libc::SIGTERM) };
+
+    let deadline = Instant::now() + KILL_GRACE;
+    while Instant::now() < deadline && matches!(child.try_wait(), Ok(None)) {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    // SAFETY: as above. The group id stays reserved while any member is alive,
+    // so this also reaches children that outlived the leader.
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    let _ = child.wait();
 }
 
-pub fn run_program_with_dry_run(program: &str, args: &[OsString], dry_run: bool) -> Result<()> {
+pub fn format_command(program: &str, args: &[OsString]) -> String {
     let args_display: Vec<String> = args
         .iter()
         .map(|a| a.to_string_lossy().into_owned())
         .collect();
 
+    format!("{} {}", program, args_display.join(" "))
+}
+
+/// Start a long-running program (e.g. swaybg) in the background and stop the
+/// instances that were running before it, so the new wallpaper replaces the old one.
+pub fn spawn_detached_with_dry_run(program: &str, args: &[OsString], dry_run: bool) -> Result<()> {
     if dry_run {
         println!(
-            "[DRY RUN] Would run: {} {}",
+            "[DRY RUN] Would replace running {} with: {}",
             program,
-            args_display.join(" ")
+            format_command(program, args)
         );
         return Ok(());
     }
 
-    println!("Running: {} {}", program, args_display.join(" "));
+    let previous = find_running_pids(program);
+
+    info!("Running in background: {}", format_command(program, args));
+    logging::record(&format!(
+        "started in background: `{}`",
+        format_command(program, args)
+    ));
+
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "Failed to spawn command `{}` (is it installed and in PATH?)",
+                program
+            )
+        })?;
+
+    if !previous.is_empty() {
+        // Old instances are stopped only after the new one is up to avoid a blank screen
+        Command::new("kill")
+            .args(&previous)
+            .status()
+            .with_context(|| format!("Failed to stop previous `{}` instances", program))?;
+    }
+
+    Ok(())
+}
+
+/// Resolve `program` like the shell does: a path when it contains a slash,
+/// otherwise the first executable file of that name in PATH
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let is_executable = |path: &Path| {
+        fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+
+    if program.contains('/') {
+        let path = expand_tilde(Path::new(program));
+        return is_executable(&path).then_some(path);
+    }
+
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+fn find_running_pids(program: &str) -> Vec<String> {
+    Command::new("pgrep")
+        .args(["-x", program])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Vec<OsString> {
+        vec!["-c".into(), script.into()]
+    }
+
+    /// Whether `pid` is running; zombies left for an absent init to reap count as gone
+    fn is_running(pid: &str) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| !stat.contains(") Z "))
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_symlink_and_mode() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("dotfiles-colors.conf");
+        let link = dir.path().join("colors.conf");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        write_atomic(&link, b"new").unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_concurrent_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.conf");
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let path = &path;
+                    scope.spawn(move || {
+                        for _ in 0..20 {
+                            write_atomic(path, format!("writer {}", i).as_bytes()).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
 
-    let mut cmd = Command::new(program);
-    cmd.args(args);
+        assert!(fs::read_to_string(&path).unwrap().starts_with("writer "));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
 
-    let output = cmd.output().with_context(|| {
-        format!(
-            "Failed to spawn command `{}` (is it installed and in PATH?)",
-            program
+    #[test]
+    fn test_capture_program_output() {
+        let output = capture_program(
+            "sh",
+            &sh("echo out; echo err >&2"),
+            Duration::from_secs(5),
+            None,
         )
-    })?;
+        .unwrap();
+
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert!(output.check_status().is_ok());
+    }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+    #[test]
+    fn test_timeout_stops_the_process_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("sleep 30 & echo $! > '{}'; wait", pid_file.display());
 
-    if !stdout.is_empty() {
-        print!("{}", stdout);
+        let started = Instant::now();
+        let error = capture_program("sh", &sh(&script), Duration::from_millis(300), None)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("timed out after 300ms"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // Signals are delivered asynchronously; give the kernel a moment to finish the kill
+        let grandchild = fs::read_to_string(&pid_file).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while is_running(grandchild.trim()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!is_running(grandchild.trim()));
     }
-    if !stderr.is_empty() {
-        eprintln!("{}", stderr);
+
+    #[test]
+    fn test_timeout_covers_background_processes_holding_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "sleep 30 & echo $! > '{}'; echo started",
+            pid_file.display()
+        );
+
+        let started = Instant::now();
+        let error = capture_program("sh", &sh(&script), Duration::from_millis(300), None)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("timed out after 300ms"), "{}", error);
+        assert!(error.contains("it exited"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let background = fs::read_to_string(&pid_file).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while is_running(background.trim()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!is_running(background.trim()));
     }
 
-    if !output.status.success() {
-        return Err(anyhow!(
-            "Command `{}` exited with status: {}",
-            program,
-            output.status
-        ));
+    #[test]
+    fn test_background_process_without_output_is_not_awaited() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "sleep 30 >/dev/null 2>&1 & echo $! > '{}'; echo started",
+            pid_file.display()
+        );
+
+        let output = capture_program("sh", &sh(&script), Duration::from_secs(5), None).unwrap();
+        assert_eq!(output.stdout, "started\n");
+
+        let background = fs::read_to_string(&pid_file).unwrap();
+        // SAFETY: only signals the sleep this test started
+        unsafe { libc::kill(background.trim().parse().unwrap(), libc::SIGKILL) };
     }
 
-    Ok(())
+    #[test]
+    fn test_timeout_kills_programs_ignoring_sigterm() {
+        let started = Instant::now();
+        let result = capture_program(
+            "sh",
+            &sh("trap '' TERM; sleep 30"),
+            Duration::from_millis(200),
+            None,
+        );
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_streamed_output_is_still_captured() {
+        let output = capture_program(
+            "sh",
+            &sh("echo one; echo two >&2; printf three"),
+            Duration::from_secs(5),
+            Some("test"),
+        )
+        .unwrap();
+
+        assert_eq!(output.stdout, "one\nthree");
+        assert_eq!(output.stderr, "two\n");
+    }
+
+    #[test]
+    fn test_prefixed_line() {
+        assert_eq!(prefixed_line("wallust", b"done\n"), "[wallust] done\n");
+        assert_eq!(prefixed_line("wallust", b"done\r\n"), "[wallust] done\n");
+        assert_eq!(
+            prefixed_line("wallust", b"no newline"),
+            "[wallust] no newline\n"
+        );
+        assert_eq!(prefixed_line("x", b"\xff"), "[x] \u{fffd}\n");
+        assert_eq!(prefixed_line("x", b"\n"), "[x]\n");
+    }
+
+    #[test]
+    fn test_failure_quotes_output() {
+        let output = capture_program(
+            "sh",
+            &sh("echo progress; echo 'bad palette' >&2; exit 2"),
+            Duration::from_secs(5),
+            None,
+        )
+        .unwrap();
+        let message = output.check_status().unwrap_err().to_string();
+
+        assert!(message.contains("exited with status"), "{}", message);
+        assert!(message.contains("    bad palette"), "{}", message);
+        assert!(!message.contains("progress"), "{}", message);
+    }
+
+    #[test]
+    fn test_failure_quotes_only_the_last_lines() {
+        let output =
+            capture_program("sh", &sh("seq 1 50; exit 1"), Duration::from_secs(5), None).unwrap();
+        let message = output.check_status().unwrap_err().to_string();
+
+        assert!(
+            message.contains("(30 earlier lines omitted)"),
+            "{}",
+            message
+        );
+        assert!(message.contains("    50"), "{}", message);
+        assert!(!message.contains("    30\n"), "{}", message);
+    }
 }